@@ -1,7 +1,10 @@
 MEMORY
 {
   /* NOTE K = KiBi = 1024 bytes */
+  /* Sectors 0 - 4. Sectors 5 and 6 are reserved for the record store (see src/storage.rs) */
+  /* STORAGE needs the 512K STM32F411xE. Sector 6 does not exist on the 256K STM32F411xC, where the store fails to mount */
   FLASH : ORIGIN = 0x08000000, LENGTH = 128K
+  STORAGE : ORIGIN = 0x08020000, LENGTH = 256K
   RAM : ORIGIN = 0x20000000, LENGTH = 32K
 }
 
@@ -0,0 +1,141 @@
+/*
+ * Proxies which allow several drivers to share one I2C peripheral.
+ *
+ * Each driver borrows its bus mutably for as long as it exists, so two drivers can not borrow the same I2C
+ * instance directly. Instead, the bus is placed in a RefCell (or a critical-section Mutex when it is shared
+ * with interrupt handlers), and each driver is given its own proxy. The proxy only borrows the bus for the
+ * duration of a single transaction.
+ *
+ * let bus = RefCell::new(i2c);
+ * let mut imu_i2c = RefCellI2c::new(&bus);
+ * let mut bmp_i2c = RefCellI2c::new(&bus);
+ * let mut imu = LSM9DS1::new(&mut imu_i2c);
+ * let mut bmp180 = BMP180::new(&mut bmp_i2c);
+ */
+use core::cell::RefCell;
+use critical_section::Mutex;
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+
+//Shares a bus between drivers used from the same execution context (e.g. all from the main loop)
+pub struct RefCellI2c<'a, I2C> {
+    bus: &'a RefCell<I2C>
+}
+
+impl<'a, I2C> RefCellI2c<'a, I2C> {
+    pub fn new(bus: &'a RefCell<I2C>) -> Self {
+        RefCellI2c {
+            bus
+        }
+    }
+}
+
+impl<'a, I2C> Write for RefCellI2c<'a, I2C> where I2C: Write {
+    type Error = I2C::Error;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.bus.borrow_mut().write(addr, bytes)
+    }
+}
+
+impl<'a, I2C> Read for RefCellI2c<'a, I2C> where I2C: Read {
+    type Error = I2C::Error;
+
+    fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.bus.borrow_mut().read(addr, buffer)
+    }
+}
+
+impl<'a, I2C> WriteRead for RefCellI2c<'a, I2C> where I2C: WriteRead {
+    type Error = I2C::Error;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.bus.borrow_mut().write_read(addr, bytes, buffer)
+    }
+}
+
+//Shares a bus between drivers used from different execution contexts, such as the main loop and interrupt handlers.
+//Every transaction runs inside a critical section, so it can not be interrupted by another user of the bus
+pub struct CriticalSectionI2c<'a, I2C> {
+    bus: &'a Mutex<RefCell<I2C>>
+}
+
+impl<'a, I2C> CriticalSectionI2c<'a, I2C> {
+    pub fn new(bus: &'a Mutex<RefCell<I2C>>) -> Self {
+        CriticalSectionI2c {
+            bus
+        }
+    }
+}
+
+impl<'a, I2C> Write for CriticalSectionI2c<'a, I2C> where I2C: Write {
+    type Error = I2C::Error;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        critical_section::with(|cs| self.bus.borrow_ref_mut(cs).write(addr, bytes))
+    }
+}
+
+impl<'a, I2C> Read for CriticalSectionI2c<'a, I2C> where I2C: Read {
+    type Error = I2C::Error;
+
+    fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        critical_section::with(|cs| self.bus.borrow_ref_mut(cs).read(addr, buffer))
+    }
+}
+
+impl<'a, I2C> WriteRead for CriticalSectionI2c<'a, I2C> where I2C: WriteRead {
+    type Error = I2C::Error;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+        critical_section::with(|cs| self.bus.borrow_ref_mut(cs).write_read(addr, bytes, buffer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+    use critical_section::Mutex;
+    use crate::mock::MockI2c;
+    use crate::pwm::servo::pca9685::pca9685_s::Pca9685;
+    use crate::sensor::Sensor;
+    use crate::sensor::barometer::bmp180::bmp180_s::BMP180;
+    use crate::sensor::imu::lsm9ds1::lsm9ds1_s::LSM9DS1;
+    use super::{CriticalSectionI2c, RefCellI2c};
+
+    fn shared_bus() -> MockI2c {
+        let mut i2c = MockI2c::new();
+        i2c.set_register(0x77, 0xD0, 0x55);
+        i2c.set_register(0x1E, 0x0F, 0x3D);
+        i2c
+    }
+
+    #[test]
+    fn refcell_proxies_share_bus() {
+        let bus = RefCell::new(shared_bus());
+        let mut imu_i2c = RefCellI2c::new(&bus);
+        let mut bmp_i2c = RefCellI2c::new(&bus);
+        let mut pca_i2c = RefCellI2c::new(&bus);
+
+        let mut imu = LSM9DS1::new(&mut imu_i2c);
+        let mut bmp180 = BMP180::new(&mut bmp_i2c);
+        let mut pca = Pca9685::new(&mut pca_i2c);
+
+        assert!(imu.sanity_check().is_ok());
+        assert!(bmp180.sanity_check().is_ok());
+        assert!(pca.initialize().is_ok());
+        assert!(pca.set_pwm(0, 0.5).is_ok());
+    }
+
+    #[test]
+    fn critical_section_proxies_share_bus() {
+        let bus = Mutex::new(RefCell::new(shared_bus()));
+        let mut imu_i2c = CriticalSectionI2c::new(&bus);
+        let mut bmp_i2c = CriticalSectionI2c::new(&bus);
+
+        let mut imu = LSM9DS1::new(&mut imu_i2c);
+        let mut bmp180 = BMP180::new(&mut bmp_i2c);
+
+        assert!(imu.sanity_check().is_ok());
+        assert!(bmp180.sanity_check().is_ok());
+    }
+}
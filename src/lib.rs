@@ -1,9 +1,14 @@
 #![deny(unsafe_code)]
 #![deny(warnings)]
 #![allow(unused)]
-#![no_main]
-#![no_std]
+#![cfg_attr(not(test), no_main)]
+#![cfg_attr(not(test), no_std)]
 
 pub mod sensor;
 pub mod usb;
-pub mod pwm;
\ No newline at end of file
+pub mod pwm;
+pub mod bus;
+pub mod storage;
+
+#[cfg(test)]
+mod mock;
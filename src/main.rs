@@ -9,8 +9,8 @@
 pub mod sensor;
 pub mod pwm;
 pub mod usb;
-
-use multi_mission_library;
+pub mod bus;
+pub mod storage;
 
 use crate::sensor::barometer::Barometer;
 use crate::sensor::Sensor;
@@ -23,6 +23,7 @@ use panic_halt as _;
 use sensor::{barometer::bmp180::bmp180_s::BMP180, imu::{lsm9ds1::{self, lsm9ds1_s::LSM9DS1}, Gyroscope, Magnetometer, Accelerometer}};
 use stm32f4xx_hal as hal;
 use usb::USB;
+use bus::RefCellI2c;
 
 use micromath::F32Ext;
 
@@ -36,11 +37,13 @@ use imu_fusion::{FusionAhrsSettings, FusionVector};
 
 use cortex_m::peripheral::scb::Exception::SysTick;
 use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::DWT;
 use core::cell::RefCell;
 
 #[entry]
 fn main() -> ! {
     let mut p = pac::Peripherals::take().unwrap();
+    let cp = cortex_m::Peripherals::take().unwrap();
 
     let rcc = p.RCC.constrain();
     let clocks = rcc.cfgr.use_hse(25.MHz()).freeze();
@@ -56,19 +59,33 @@ fn main() -> ! {
     let scl = gpiob.pb6;
     let sda = gpiob.pb7;
 
-    let mut i2c = p.I2C1.i2c(
+    //The bus is shared between the drivers through RefCellI2c proxies, so several devices can be used at once
+    let i2c = RefCell::new(p.I2C1.i2c(
         (scl, sda),
         Mode::Standard {
             frequency: 100.kHz()
         },
         &clocks
-    );
+    ));
 
     let mut delay = p.TIM1.delay_ms(&clocks);
 
-    let mut imu = LSM9DS1::new(&mut i2c);
-    imu.init();
-    imu.calibrate();
+    //Cycle counter used to timestamp the IMU samples
+    let mono = MonoTimer::new(cp.DWT, cp.DCB, &clocks);
+    let cycles_per_us = mono.frequency().raw() / 1_000_000;
+    let mut last_cycles = DWT::cycle_count();
+    let mut cycle_remainder: u32 = 0;
+    let mut timestamp_us: u32 = 0;
+
+    let mut imu_i2c = RefCellI2c::new(&i2c);
+    let mut imu = LSM9DS1::new(&mut imu_i2c);
+    if imu.init().is_err() {
+        //The state of the IMU is left as SensorState::ERROR, and every read below will fail until it is re-initialized
+        usb.println("IMU init failed");
+    } else if imu.calibrate().is_err() {
+        //The board was moving, so the gyro is read without bias correction
+        usb.println("IMU gyro calibration failed");
+    }
 
     let ahrs_settings = FusionAhrsSettings::new();
     let data_rate = 35;
@@ -79,17 +96,30 @@ fn main() -> ! {
     let mut i: u32 = 0;
 
     loop {
-        let acc = imu.read_acceleration();
-        let g = imu.read_gyro();
-        let m = imu.read_magnetometer();
+        //One read of the cycle counter per sample, carrying the cycles left over so that the timestamp does not drift
+        let cycles = DWT::cycle_count();
+        let elapsed = cycles.wrapping_sub(last_cycles) as u64 + cycle_remainder as u64;
+        last_cycles = cycles;
+        timestamp_us = timestamp_us.wrapping_add((elapsed / cycles_per_us as u64) as u32);
+        cycle_remainder = (elapsed % cycles_per_us as u64) as u32;
+
+        //Skip this sample if the read failed, rather than halting the board
+        let (acc, g, m) = match imu.read_all(timestamp_us) {
+            Ok(sample) => (sample.acceleration, sample.gyro, sample.magnetometer),
+            Err(_) => {
+                usb.println("IMU read failed");
+                delay.delay_ms(10);
+                continue;
+            }
+        };
         
-        let acc_fv = FusionVector::new(acc.0 as f32, acc.1 as f32, acc.2 as f32);
-        let g_fv = FusionVector::new(g.0 as f32, g.1 as f32, g.2 as f32);
-        let m_fv = FusionVector::new(m.0 as f32, m.1 as f32, m.2 as f32);
+        let acc_fv = FusionVector::new(acc.0, acc.1, acc.2);
+        let g_fv = FusionVector::new(g.0, g.1, g.2);
+        let m_fv = FusionVector::new(m.0, m.1, m.2);
 
-        let heading = (m.0 as f32).atan2(m.1 as f32).to_degrees();
-        let pitch = (acc.0 as f32).atan2(((acc.1 * acc.1) + (acc.2 * acc.2)).sqrt()).to_degrees();
-        let roll = (acc.1 as f32).atan2(((acc.0 * acc.0) + (acc.2 * acc.2)).sqrt()).to_degrees();
+        let heading = m.0.atan2(m.1).to_degrees();
+        let pitch = acc.0.atan2(((acc.1 * acc.1) + (acc.2 * acc.2)).sqrt()).to_degrees();
+        let roll = acc.1.atan2(((acc.0 * acc.0) + (acc.2 * acc.2)).sqrt()).to_degrees();
 
         //timestamp += 1.0 / data_rate as f32;
         //fusion.update(g_fv, acc_fv, m_fv, timestamp as f32);
@@ -106,12 +136,14 @@ fn main() -> ! {
         
         let write_res = write!(message, "Orientation: {}, {}, {}", roll, pitch, heading);
 
-        usb.println(&message.as_str());
+        usb.println(message.as_str());
         delay.delay_ms(10);
     }
 
     /*
-    let mut driver = Pca9685::new(&mut i2c);
+    let mut pca_i2c = RefCellI2c::new(&i2c);
+    let mut driver = Pca9685::new(&mut pca_i2c);
+    driver.initialize();
 
     let mut i = 0.0;
     let min_ds = 0.1;
@@ -139,7 +171,8 @@ fn main() -> ! {
 
     //Initialize the sensor
     /*
-    let mut lsm9ds1 = LSM9DS1::new(&mut i2c);
+    let mut lsm9ds1_i2c = RefCellI2c::new(&i2c);
+    let mut lsm9ds1 = LSM9DS1::new(&mut lsm9ds1_i2c);
     let init_res = lsm9ds1.init();
     if init_res.is_err() {
         loop {}
@@ -158,7 +191,8 @@ fn main() -> ! {
     */
 
     /*
-    let mut bmp180 = BMP180::new(&mut i2c);
+    let mut bmp180_i2c = RefCellI2c::new(&i2c);
+    let mut bmp180 = BMP180::new(&mut bmp180_i2c);
     let init_res = bmp180.init();
     if init_res.is_err() {
         loop {}
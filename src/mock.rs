@@ -0,0 +1,434 @@
+/*
+ * Host-side test doubles for the embedded-hal traits used by the drivers.
+ *
+ * MockI2c behaves like a bus with register-based devices on it: a write sets the register pointer of the
+ * addressed device and stores any data bytes, and a read returns bytes starting at the register pointer.
+ * Both auto-increment the register pointer, as most sensors do. Devices which only auto-increment when the MSB of the
+ * sub-address is set (such as the LSM9DS1 magnetometer) can be modelled with set_auto_increment_bit. Responses can also be scripted per register,
+ * for registers (such as conversion results) that should return a different value on each read.
+ * Every write is recorded so tests can check exactly what a driver sent.
+ *
+ * MockSpi puts the same register model behind chip-selects. The first byte of each transfer is the register address,
+ * with bit 7 set for reads; devices marked with set_auto_increment_bit only auto-increment when bit 6 is set.
+ *
+ * RamFlash behaves like a small NOR flash: erasing sets a page to 0xFF, and writes may only clear bits.
+ */
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::Infallible;
+use std::rc::Rc;
+use std::vec::Vec;
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+use embedded_hal::blocking::spi;
+use embedded_hal::digital::v2::OutputPin;
+use embedded_storage::nor_flash::{ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MockError {
+    Nack,
+    NotSelected //An SPI transfer without a chip-select asserted
+}
+
+#[derive(Default)]
+pub struct MockI2c {
+    registers: HashMap<(u8, u8), u8>,
+    read_only: HashSet<(u8, u8)>,
+    pointers: HashMap<u8, u8>,
+    auto_increment_bit: HashSet<u8>,
+    no_increment: HashSet<u8>,
+    scripted_reads: VecDeque<(u8, u8, Vec<u8>)>,
+    pub writes: Vec<(u8, Vec<u8>)>,
+    pub nack: bool
+}
+
+impl MockI2c {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_register(&mut self, addr: u8, register: u8, value: u8) {
+        self.registers.insert((addr, register), value);
+    }
+
+    pub fn set_registers(&mut self, addr: u8, start_register: u8, values: &[u8]) {
+        for (i, value) in values.iter().enumerate() {
+            self.set_register(addr, start_register.wrapping_add(i as u8), *value);
+        }
+    }
+
+    //Writes to a read-only register are recorded but do not change its value
+    pub fn set_read_only(&mut self, addr: u8, register: u8) {
+        self.read_only.insert((addr, register));
+    }
+
+    //The device only auto-increments its register pointer when bit 7 of the sub-address is set
+    pub fn set_auto_increment_bit(&mut self, addr: u8) {
+        self.auto_increment_bit.insert(addr);
+    }
+
+    pub fn register(&self, addr: u8, register: u8) -> u8 {
+        *self.registers.get(&(addr, register)).unwrap_or(&0)
+    }
+
+    //Queues a response for the next read of a register. Scripted responses are used in order, before the register values
+    pub fn script_read(&mut self, addr: u8, register: u8, values: &[u8]) {
+        self.scripted_reads.push_back((addr, register, values.to_vec()));
+    }
+
+    //All register writes sent to a device, as (register, data) pairs. Writes which only set the register pointer are skipped
+    pub fn register_writes(&self, addr: u8) -> Vec<(u8, Vec<u8>)> {
+        self.writes.iter()
+            .filter(|(a, bytes)| *a == addr && bytes.len() > 1)
+            .map(|(_, bytes)| (bytes[0], bytes[1..].to_vec()))
+            .collect()
+    }
+
+    fn set_pointer(&mut self, addr: u8, sub_address: u8) {
+        if self.auto_increment_bit.contains(&addr) {
+            self.pointers.insert(addr, sub_address & 0x7F);
+            if sub_address & 0x80 == 0 {
+                self.no_increment.insert(addr);
+            } else {
+                self.no_increment.remove(&addr);
+            }
+        } else {
+            self.pointers.insert(addr, sub_address);
+        }
+    }
+
+    //Offset of the i-th byte of a transfer from the register pointer
+    fn increment(&self, addr: u8, i: usize) -> u8 {
+        if self.no_increment.contains(&addr) { 0 } else { i as u8 }
+    }
+
+    fn read_from_pointer(&mut self, addr: u8, buffer: &mut [u8]) {
+        let register = *self.pointers.get(&addr).unwrap_or(&0);
+
+        let scripted = self.scripted_reads.iter().position(|(a, r, _)| *a == addr && *r == register);
+        if let Some(index) = scripted {
+            let (_, _, values) = self.scripted_reads.remove(index).unwrap();
+            buffer.copy_from_slice(&values[..buffer.len()]);
+        } else {
+            for (i, byte) in buffer.iter_mut().enumerate() {
+                *byte = self.register(addr, register.wrapping_add(self.increment(addr, i)));
+            }
+        }
+
+        self.pointers.insert(addr, register.wrapping_add(self.increment(addr, buffer.len())));
+    }
+}
+
+impl Write for MockI2c {
+    type Error = MockError;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        if self.nack {
+            return Err(MockError::Nack);
+        }
+
+        self.writes.push((addr, bytes.to_vec()));
+
+        if let Some((sub_address, data)) = bytes.split_first() {
+            self.set_pointer(addr, *sub_address);
+            let register = *self.pointers.get(&addr).unwrap_or(&0);
+            for (i, value) in data.iter().enumerate() {
+                let target = register.wrapping_add(self.increment(addr, i));
+                if !self.read_only.contains(&(addr, target)) {
+                    self.set_register(addr, target, *value);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for MockI2c {
+    type Error = MockError;
+
+    fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        if self.nack {
+            return Err(MockError::Nack);
+        }
+
+        self.read_from_pointer(addr, buffer);
+
+        Ok(())
+    }
+}
+
+impl WriteRead for MockI2c {
+    type Error = MockError;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+        if self.nack {
+            return Err(MockError::Nack);
+        }
+
+        self.set_pointer(addr, bytes[0]);
+        self.read_from_pointer(addr, buffer);
+
+        Ok(())
+    }
+}
+
+//The chip-select currently asserted, and a count of selections so that each one starts a new transfer
+#[derive(Default)]
+struct Selection {
+    device: Cell<Option<u8>>,
+    count: Cell<u32>
+}
+
+#[derive(Default)]
+pub struct MockSpi {
+    pub devices: MockI2c, //Register model, with the id of each chip-select used as the device address
+    selection: Rc<Selection>,
+    transfer: Option<(u32, u8)>, //Selection count and address byte of the transfer in progress
+    pub addresses: Vec<(u8, u8)> //Address byte of every transfer, as (chip-select id, address)
+}
+
+impl MockSpi {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //Active-low chip-select for the device with the given id
+    pub fn chip_select(&self, id: u8) -> MockChipSelect {
+        MockChipSelect {
+            id,
+            selection: self.selection.clone()
+        }
+    }
+
+    //Sub-address for the register model, with bit 6 (auto-increment) moved to bit 7 as MockI2c expects
+    fn sub_address(address: u8) -> u8 {
+        (address & 0x3F) | ((address & 0x40) << 1)
+    }
+
+    //Returns the selected device and the address byte of its transfer, or None if bytes is the address byte
+    fn start_transfer(&mut self, bytes: &[u8]) -> Result<(u8, Option<u8>), MockError> {
+        let device = self.selection.device.get().ok_or(MockError::NotSelected)?;
+        let count = self.selection.count.get();
+        match self.transfer {
+            Some((transfer_count, address)) if transfer_count == count => Ok((device, Some(address))),
+            _ => {
+                self.transfer = Some((count, bytes[0]));
+                self.addresses.push((device, bytes[0]));
+                Ok((device, None))
+            }
+        }
+    }
+}
+
+impl spi::Write<u8> for MockSpi {
+    type Error = MockError;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        let (device, address) = self.start_transfer(words)?;
+        let (address, data) = match address {
+            Some(address) => (address, words),
+            None => (words[0], &words[1..])
+        };
+
+        if !data.is_empty() && address & 0x80 == 0 {
+            let mut bytes = vec![Self::sub_address(address)];
+            bytes.extend_from_slice(data);
+            Write::write(&mut self.devices, device, &bytes)?;
+        }
+        Ok(())
+    }
+}
+
+impl spi::Transfer<u8> for MockSpi {
+    type Error = MockError;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        let (device, address) = self.start_transfer(words)?;
+        if let Some(address) = address.filter(|address| address & 0x80 != 0) {
+            self.devices.write_read(device, &[Self::sub_address(address)], words)?;
+        }
+        Ok(words)
+    }
+}
+
+pub struct MockChipSelect {
+    id: u8,
+    selection: Rc<Selection>
+}
+
+impl OutputPin for MockChipSelect {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.selection.device.set(Some(self.id));
+        self.selection.count.set(self.selection.count.get() + 1);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        if self.selection.device.get() == Some(self.id) {
+            self.selection.device.set(None);
+        }
+        Ok(())
+    }
+}
+
+//Delay which returns immediately, keeping track of the total time that would have been waited
+#[derive(Default)]
+pub struct MockDelay {
+    pub elapsed_ms: u32
+}
+
+impl DelayMs<u32> for MockDelay {
+    fn delay_ms(&mut self, ms: u32) {
+        self.elapsed_ms += ms;
+    }
+}
+
+pub const RAM_FLASH_PAGE_SIZE: usize = 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RamFlashError {
+    NotAligned,
+    OutOfBounds,
+    NotErased, //A write tried to set a bit which was not erased
+    PowerLoss
+}
+
+impl NorFlashError for RamFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            RamFlashError::NotAligned => NorFlashErrorKind::NotAligned,
+            RamFlashError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            _ => NorFlashErrorKind::Other
+        }
+    }
+}
+
+pub struct RamFlash {
+    pub data: Vec<u8>,
+    pub erase_counts: Vec<u32>, //Number of erases of each page
+    pub write_budget: Option<usize> //Writes allowed before the flash stops responding, to simulate a power loss
+}
+
+impl RamFlash {
+    //A blank flash of the given number of pages
+    pub fn new(pages: usize) -> Self {
+        RamFlash {
+            data: vec![0xFF; pages * RAM_FLASH_PAGE_SIZE],
+            erase_counts: vec![0; pages],
+            write_budget: None
+        }
+    }
+}
+
+impl ErrorType for RamFlash {
+    type Error = RamFlashError;
+}
+
+impl ReadNorFlash for RamFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let start = offset as usize;
+        let end = start + bytes.len();
+        if end > self.data.len() {
+            return Err(RamFlashError::OutOfBounds);
+        }
+
+        bytes.copy_from_slice(&self.data[start..end]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl NorFlash for RamFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = RAM_FLASH_PAGE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        let (from, to) = (from as usize, to as usize);
+        if from % Self::ERASE_SIZE != 0 || to % Self::ERASE_SIZE != 0 {
+            return Err(RamFlashError::NotAligned);
+        }
+        if from > to || to > self.data.len() {
+            return Err(RamFlashError::OutOfBounds);
+        }
+
+        self.data[from..to].fill(0xFF);
+        for page in from / Self::ERASE_SIZE..to / Self::ERASE_SIZE {
+            self.erase_counts[page] += 1;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let start = offset as usize;
+        if start % Self::WRITE_SIZE != 0 || bytes.len() % Self::WRITE_SIZE != 0 {
+            return Err(RamFlashError::NotAligned);
+        }
+        if start + bytes.len() > self.data.len() {
+            return Err(RamFlashError::OutOfBounds);
+        }
+        if let Some(budget) = self.write_budget {
+            if budget == 0 {
+                return Err(RamFlashError::PowerLoss);
+            }
+            self.write_budget = Some(budget - 1);
+        }
+
+        let target = &mut self.data[start..start + bytes.len()];
+        if target.iter().zip(bytes).any(|(old, new)| old & new != *new) {
+            return Err(RamFlashError::NotErased);
+        }
+        target.copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/*
+ * Host stand-in for the critical section implementation cortex-m provides on the MCU.
+ * A global lock is taken by the outermost section on a thread, so sections may nest.
+ */
+#[allow(unsafe_code)]
+mod host_critical_section {
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use critical_section::RawRestoreState;
+
+    struct HostCriticalSection;
+    critical_section::set_impl!(HostCriticalSection);
+
+    static LOCKED: AtomicBool = AtomicBool::new(false);
+
+    std::thread_local! {
+        static DEPTH: Cell<u32> = const { Cell::new(0) };
+    }
+
+    unsafe impl critical_section::Impl for HostCriticalSection {
+        unsafe fn acquire() -> RawRestoreState {
+            DEPTH.with(|depth| {
+                if depth.get() == 0 {
+                    while LOCKED.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+                        std::hint::spin_loop();
+                    }
+                }
+                depth.set(depth.get() + 1);
+            });
+            RawRestoreState::default()
+        }
+
+        unsafe fn release(_: RawRestoreState) {
+            DEPTH.with(|depth| {
+                depth.set(depth.get() - 1);
+                if depth.get() == 0 {
+                    LOCKED.store(false, Ordering::Release);
+                }
+            });
+        }
+    }
+}
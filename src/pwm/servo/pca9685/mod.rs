@@ -1,3 +1,6 @@
+#[allow(clippy::module_inception)]
 pub mod pca9685;
 pub mod pca9685_s;
-pub mod pcachannel;
\ No newline at end of file
+pub mod pcachannel;
+#[cfg(test)]
+mod tests;
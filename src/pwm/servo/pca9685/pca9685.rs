@@ -1,48 +1,46 @@
-use cortex_m::asm::nop;
-/**
+/*
  * LED_ON stores the counter value at which the PWM signal should go high
  * LED_OFF stores the counter value at which the PWM signal should go low
  * Therefore, if a delay is needed, LED_ON will be the delay value, and LED_OFF will be the delay value + the on time
  */
 
-use stm32f4xx_hal::{i2c::{I2c, Instance as I2cInstance}, pac::TIM1, timer::DelayMs};
+use core::fmt::Debug;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
 use embedded_hal::prelude::_embedded_hal_blocking_delay_DelayMs; //Bring the DelayMs trait into scope
 use super::pca9685_s::Pca9685;
 
-pub enum SetPwmError {
-    InvalidChannel
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SetPwmError<E> {
+    InvalidChannel,
+    Bus(E)
 }
 
-impl<'a, T  > Pca9685<'a, T> where T: I2cInstance {
-    pub fn new(i2c: &'a mut I2c<T>) -> Self {
-        let mut device = Pca9685 {
+impl<'a, I2C, E> Pca9685<'a, I2C> where I2C: Write<Error = E> + WriteRead<Error = E>, E: Debug {
+    //Does not communicate with the device. initialize must be called before setting any outputs
+    pub fn new(i2c: &'a mut I2C) -> Self {
+        Pca9685 {
             addr: 0x40,
             i2c,
-        };
-        device.initialize();
-        device
+        }
     }
 
     //Take the device out of sleep mode and enable its internal oscillator
-    pub fn initialize(&mut self) {
+    pub fn initialize(&mut self) -> Result<(), E> {
         let mut initial_mode_data: [u8; 1] = [0; 1];
         let mut final_mode_data: [u8; 1] = [0; 1];
 
         //Read existing value from mode register
-        self.i2c.write_read(self.addr, &[0x00], &mut initial_mode_data).unwrap();
-        nop();
+        self.i2c.write_read(self.addr, &[0x00], &mut initial_mode_data)?;
 
         let mut mode1 = initial_mode_data[0];
-        mode1 = mode1 & 0b0111; //Set bit 4 low while keeping other bits with original value
-        mode1 = mode1 | 0b10000000; //Set bit 7 high while keeping other bits with original value
+        mode1 &= 0b0111; //Set bit 4 low while keeping other bits with original value
+        mode1 |= 0b10000000; //Set bit 7 high while keeping other bits with original value
 
         //Write new value to mode register
-        let write_res = self.i2c.write(self.addr, &[0x00, mode1]);
-        nop();
+        self.i2c.write(self.addr, &[0x00, mode1])?;
 
         //Read existing value from mode register
-        self.i2c.write_read(self.addr, &[0x00], &mut final_mode_data).unwrap();
-        nop();
+        self.i2c.write_read(self.addr, &[0x00], &mut final_mode_data)
     }
 
     // pub fn set_angle(&mut self, angle: u16) -> Result<(), ()> {
@@ -50,7 +48,7 @@ impl<'a, T  > Pca9685<'a, T> where T: I2cInstance {
     //     return 
     // }
 
-    pub fn set_pwm(&mut self, channel: u8, duty_cycle: f32) -> Result<(), SetPwmError> {
+    pub fn set_pwm(&mut self, channel: u8, duty_cycle: f32) -> Result<(), SetPwmError<E>> {
         if channel > 15 {
             return Err(SetPwmError::InvalidChannel);
         }
@@ -66,16 +64,16 @@ impl<'a, T  > Pca9685<'a, T> where T: I2cInstance {
         data[2] = (on_time & 0xFF) as u8;
         data[3] = ((on_time >> 8) & 0xFF) as u8;
 
-        self.send_pwm_value(addr, data[0]);
-        self.send_pwm_value(addr + 1, data[1]);
-        self.send_pwm_value(addr + 2, data[2]);
-        self.send_pwm_value(addr + 3, data[3]);
+        self.send_pwm_value(addr, data[0])?;
+        self.send_pwm_value(addr + 1, data[1])?;
+        self.send_pwm_value(addr + 2, data[2])?;
+        self.send_pwm_value(addr + 3, data[3])?;
 
         Ok(())
     }
 
-    fn send_pwm_value(&mut self, register_addr: u8, value: u8) {
+    fn send_pwm_value(&mut self, register_addr: u8, value: u8) -> Result<(), SetPwmError<E>> {
         let register_with_data: [u8; 2] = [register_addr, value];
-        self.i2c.write(self.addr, &register_with_data).unwrap();
+        self.i2c.write(self.addr, &register_with_data).map_err(SetPwmError::Bus)
     }
 }
\ No newline at end of file
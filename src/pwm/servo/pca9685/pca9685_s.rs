@@ -1,13 +1,6 @@
-use embedded_hal::blocking::i2c::WriteRead;
-use stm32f4xx_hal::{hal, pac::TIM1};
-use stm32f4xx_hal::i2c::{I2c, Instance as I2cInstance};
-use stm32f4xx_hal::timer::{DelayMs, Timer, TimerExt, Instance as TimerInstance};
-use stm32f4xx_hal::rcc::Clocks;
-use embedded_hal::prelude::*;
-
-pub struct Pca9685<'a, T> where T: I2cInstance {
+pub struct Pca9685<'a, I2C> {
     pub addr: u8,
-    pub i2c: &'a mut I2c<T>, //Allows for the BMP180 struct to not take ownership of the I2C instance, which means multiple devices can be on the same bus :)
+    pub i2c: &'a mut I2C, //Any bus implementing the embedded-hal blocking I2C traits. Borrowed so that the struct does not take ownership of the I2C instance
 }
 
 /*
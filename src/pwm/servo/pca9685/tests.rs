@@ -0,0 +1,62 @@
+use crate::mock::{MockError, MockI2c};
+use super::pca9685::SetPwmError;
+use super::pca9685_s::Pca9685;
+
+const ADDR: u8 = 0x40;
+
+fn pca_bus() -> MockI2c {
+    let mut i2c = MockI2c::new();
+    i2c.set_register(ADDR, 0x00, 0x11); //MODE1 after power-on: SLEEP and ALLCALL set
+    i2c
+}
+
+#[test]
+fn new_does_not_touch_bus() {
+    let mut i2c = pca_bus();
+    Pca9685::new(&mut i2c);
+
+    assert!(i2c.writes.is_empty());
+}
+
+#[test]
+fn initialize_wakes_device() {
+    let mut i2c = pca_bus();
+    let mut driver = Pca9685::new(&mut i2c);
+    assert!(driver.initialize().is_ok());
+    drop(driver);
+
+    assert_eq!(i2c.register_writes(ADDR), [(0x00, vec![0x81])]);
+}
+
+#[test]
+fn set_pwm_writes_led_registers() {
+    let mut i2c = pca_bus();
+    let mut driver = Pca9685::new(&mut i2c);
+    driver.initialize().ok();
+    assert!(driver.set_pwm(1, 0.5).is_ok());
+    drop(driver);
+
+    //On time of 2047 counts, starting at the beginning of the period
+    assert_eq!(i2c.register_writes(ADDR)[1..], [
+        (0x0A, vec![0x00]),
+        (0x0B, vec![0x00]),
+        (0x0C, vec![0xFF]),
+        (0x0D, vec![0x07])
+    ]);
+}
+
+#[test]
+fn set_pwm_rejects_invalid_channel() {
+    let mut i2c = pca_bus();
+    let mut driver = Pca9685::new(&mut i2c);
+    assert!(matches!(driver.set_pwm(16, 0.5), Err(SetPwmError::InvalidChannel)));
+}
+
+#[test]
+fn bus_errors_are_returned() {
+    let mut i2c = pca_bus();
+    i2c.nack = true;
+    let mut driver = Pca9685::new(&mut i2c);
+    assert_eq!(driver.initialize(), Err(MockError::Nack));
+    assert_eq!(driver.set_pwm(0, 0.5), Err(SetPwmError::Bus(MockError::Nack)));
+}
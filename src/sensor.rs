@@ -1,13 +1,36 @@
 pub mod barometer;
+pub mod calibration;
 pub mod imu;
-use stm32f4xx_hal::i2c::{I2c, Instance as I2cInstance};
 
-pub enum SensorError {
-    I2CError,
-    NotFound,
-    FailedToCalibrate
+//Errors returned by sensor drivers. E is the error type of the bus the sensor is connected to
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SensorError<E = ()> {
+    Bus(E), //The bus transaction failed
+    WrongId {
+        expected: u8,
+        found: u8
+    },
+    FailedToCalibrate,
+    Timeout,
+    Busy, //A conversion is already in progress
+    InvalidConfiguration //The requested configuration is not supported, or was not applied by the sensor
 }
 
+impl<E> SensorError<E> {
+    //Drops the bus error, so that the error can be stored in a SensorState without knowing the bus type
+    pub fn kind(&self) -> SensorError {
+        match self {
+            SensorError::Bus(_) => SensorError::Bus(()),
+            SensorError::WrongId { expected, found } => SensorError::WrongId { expected: *expected, found: *found },
+            SensorError::FailedToCalibrate => SensorError::FailedToCalibrate,
+            SensorError::Timeout => SensorError::Timeout,
+            SensorError::Busy => SensorError::Busy,
+            SensorError::InvalidConfiguration => SensorError::InvalidConfiguration
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum SensorState {
     INITIAL,
     STARTUP,
@@ -16,8 +39,18 @@ pub enum SensorState {
     ERROR(SensorError)
 }
 
+impl SensorState {
+    //Records a failed bus transaction, and wraps the bus error so it can be returned
+    pub(crate) fn bus_error<E>(&mut self, error: E) -> SensorError<E> {
+        *self = SensorState::ERROR(SensorError::Bus(()));
+        SensorError::Bus(error)
+    }
+}
+
 pub trait Sensor {
-    fn init(&mut self) -> Result<(), ()>;
-    fn calibrate(&mut self) -> Result<(), ()>;
+    type BusError;
+
+    fn init(&mut self) -> Result<(), SensorError<Self::BusError>>;
+    fn calibrate(&mut self) -> Result<(), SensorError<Self::BusError>>;
     fn sensor_state(&self) -> &SensorState;
-}
\ No newline at end of file
+}
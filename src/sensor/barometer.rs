@@ -1,13 +1,125 @@
 pub mod bmp180;
 use embedded_hal::blocking::delay::DelayMs;
-use stm32f4xx_hal::{pac::TIM1, timer::Delay};
+use micromath::F32Ext;
 
-use crate::sensor::Sensor;
+use crate::sensor::{Sensor, SensorError};
+use crate::sensor::calibration::{CalibrationError, PayloadReader, PayloadWriter, Persistent, KIND_ALTITUDE_REFERENCE};
+
+//Standard atmospheric pressure at sea level in Pa
+pub const STANDARD_SEA_LEVEL_PRESSURE: f32 = 101325.0;
+
+//Reference values used when converting pressure readings into altitude
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AltitudeReference {
+    pub sea_level_pressure: f32, //Pa
+    pub ground_pressure: Option<f32> //Pa, captured at the launch site by Barometer::zero_altitude
+}
+
+impl Default for AltitudeReference {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AltitudeReference {
+    pub fn new() -> Self {
+        AltitudeReference {
+            sea_level_pressure: STANDARD_SEA_LEVEL_PRESSURE,
+            ground_pressure: None
+        }
+    }
+}
+
+impl Persistent for AltitudeReference {
+    const KIND: u8 = KIND_ALTITUDE_REFERENCE;
+    const VERSION: u8 = 1;
+    const PAYLOAD_LEN: usize = 9;
+
+    fn encode_payload(&self, payload: &mut PayloadWriter) {
+        payload.write_f32(self.sea_level_pressure);
+        payload.write_u8(self.ground_pressure.is_some() as u8);
+        payload.write_f32(self.ground_pressure.unwrap_or(0.0));
+    }
+
+    fn decode_payload(payload: &mut PayloadReader) -> Self {
+        let sea_level_pressure = payload.read_f32();
+        let has_ground_pressure = payload.read_u8() != 0;
+        let ground_pressure = payload.read_f32();
+
+        AltitudeReference {
+            sea_level_pressure,
+            ground_pressure: if has_ground_pressure { Some(ground_pressure) } else { None }
+        }
+    }
+}
 
 pub trait Barometer: Sensor {
-    fn read_pressure(&mut self) -> i32;
-    fn read_temperature(&mut self, delay: &mut Delay<TIM1, 1000>) -> i32;
+    //Any ms delay implementing the embedded-hal DelayMs trait can be used, such as a HAL timer, SysTick or a mock in tests
+    fn read_pressure<D: DelayMs<u32>>(&mut self, delay: &mut D) -> Result<i32, SensorError<Self::BusError>>;
+    fn read_temperature<D: DelayMs<u32>>(&mut self, delay: &mut D) -> Result<i32, SensorError<Self::BusError>>;
 
     fn pressure(&self) -> i32;
     fn temperature(&self) -> i32;
-}
\ No newline at end of file
+
+    fn altitude_reference(&self) -> &AltitudeReference;
+    fn altitude_reference_mut(&mut self) -> &mut AltitudeReference;
+
+    fn sea_level_pressure(&self) -> f32 {
+        self.altitude_reference().sea_level_pressure
+    }
+
+    fn set_sea_level_pressure(&mut self, pressure: f32) {
+        self.altitude_reference_mut().sea_level_pressure = pressure;
+    }
+
+    //Converts a pressure in Pa into an altitude in m above sea level using the international barometric formula
+    fn altitude_at_pressure(&self, pressure: f32) -> f32 {
+        44330.0 * (1.0 - (pressure / self.sea_level_pressure()).powf(1.0 / 5.255))
+    }
+
+    //Altitude in m above sea level, based on the last pressure reading
+    fn altitude(&self) -> f32 {
+        self.altitude_at_pressure(self.pressure() as f32)
+    }
+
+    //Stores the last pressure reading as the ground reference, so that relative_altitude reads 0 at the current location.
+    //Returns SensorError::InvalidConfiguration if no pressure has been read yet
+    fn zero_altitude(&mut self) -> Result<(), SensorError<Self::BusError>> {
+        if self.pressure() <= 0 {
+            return Err(SensorError::InvalidConfiguration);
+        }
+
+        self.altitude_reference_mut().ground_pressure = Some(self.pressure() as f32);
+        Ok(())
+    }
+
+    //Altitude in m above the ground reference. Falls back to the altitude above sea level if no ground reference has been captured
+    fn relative_altitude(&self) -> f32 {
+        match self.altitude_reference().ground_pressure {
+            Some(ground_pressure) => self.altitude() - self.altitude_at_pressure(ground_pressure),
+            None => self.altitude()
+        }
+    }
+
+    //Calculates the sea level pressure in Pa from the last pressure reading, given the current altitude in m above sea level
+    fn sea_level_pressure_at_altitude(&self, altitude: f32) -> f32 {
+        self.pressure() as f32 / (1.0 - altitude / 44330.0).powf(5.255)
+    }
+
+    //Sets the sea level pressure so that altitude matches a known altitude in m above sea level
+    fn calibrate_sea_level_pressure(&mut self, altitude: f32) {
+        let pressure = self.sea_level_pressure_at_altitude(altitude);
+        self.set_sea_level_pressure(pressure);
+    }
+
+    //Encodes the sea level pressure and ground reference into a record, returning its length in bytes
+    fn store_altitude_reference(&self, buffer: &mut [u8]) -> Result<usize, CalibrationError> {
+        self.altitude_reference().encode(buffer)
+    }
+
+    //Restores a reference saved by store_altitude_reference. The current reference is kept if the record is invalid
+    fn load_altitude_reference(&mut self, buffer: &[u8]) -> Result<(), CalibrationError> {
+        *self.altitude_reference_mut() = AltitudeReference::decode(buffer)?;
+        Ok(())
+    }
+}
@@ -1,4 +1,8 @@
 pub mod barometer;
 pub mod sensor;
+#[allow(clippy::module_inception)]
 pub mod bmp180;
-pub mod bmp180_s;
\ No newline at end of file
+pub mod bmp180_s;
+pub mod conversion;
+#[cfg(test)]
+mod tests;
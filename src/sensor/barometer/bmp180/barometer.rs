@@ -1,11 +1,11 @@
-use embedded_hal::prelude::_embedded_hal_blocking_delay_DelayMs;
-use stm32f4xx_hal::pac::TIM1;
-use stm32f4xx_hal::timer::{Delay, DelayMs}; //Bring the DelayMs trait into scope
-use crate::sensor::I2cInstance;
-use super::super::Barometer;
+use embedded_hal::blocking::delay::DelayMs;
+use core::fmt::Debug;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use crate::sensor::SensorError;
+use super::super::{AltitudeReference, Barometer};
 use super::bmp180_s::BMP180;
 
-impl<'a, T> Barometer for BMP180<'a, T> where T: I2cInstance {
+impl<'a, I2C, E> Barometer for BMP180<'a, I2C> where I2C: Write<Error = E> + WriteRead<Error = E>, E: Debug {
     fn pressure(&self) -> i32 {
         self.data.pressure
     }
@@ -14,35 +14,38 @@ impl<'a, T> Barometer for BMP180<'a, T> where T: I2cInstance {
         self.data.temperature
     }
 
-    fn read_pressure(&mut self) -> i32 {
-        self.data.pressure = 0; //TODO
-        return 0
+    fn altitude_reference(&self) -> &AltitudeReference {
+        &self.altitude_reference
     }
 
-    //Reads the temperature in celcius and stores it
-    fn read_temperature<'b>(&mut self, delay: &mut Delay<TIM1, 1000>) -> i32 {
-        self.i2c.write(self.addr, &[self.register_map.ctrl_meas_addr, 0x2E]).unwrap();
+    fn altitude_reference_mut(&mut self) -> &mut AltitudeReference {
+        &mut self.altitude_reference
+    }
 
-        delay.delay_ms(5_u32);
+    //Reads the pressure in Pa and stores it. A temperature reading is taken first, as the pressure compensation depends on it
+    fn read_pressure<D: DelayMs<u32>>(&mut self, delay: &mut D) -> Result<i32, SensorError<E>> {
+        let ut = self.read_raw_temperature(delay)?;
+        let b5 = self.compute_b5(ut);
+        self.b5 = Some(b5);
+        self.data.temperature = self.compensate_temperature(b5) / 10;
 
-        let mut rx_buffer: [u8; 2] = [0; 2];
-        let mut rx_word: i16;
-        self.i2c.write(self.addr, &[self.register_map.meas_out_msb_addr]).unwrap();
-        self.i2c.read(self.addr, &mut rx_buffer).unwrap();
-        rx_word = (rx_buffer[0] as i16) << 8;
+        let up = self.read_raw_pressure(delay)?;
+        let p = self.compensate_pressure(up, b5);
 
-        self.i2c.write(self.addr, &[self.register_map.meas_out_lsb_addr]).unwrap();
-        self.i2c.read(self.addr, &mut rx_buffer).unwrap();
-        rx_word |= rx_buffer[0] as i16;
+        self.data.pressure = p;
 
-        //Correct the temperature value
-        let x1 = (rx_word as i32 - self.calib_coeffs.ac6 as i32) * (self.calib_coeffs.ac5 as i32) >> 15;
-        let x2 = ((self.calib_coeffs.mc as i32) << 11) / (x1 + self.calib_coeffs.md as i32);
-        let b5 = x1 + x2;
-        let t = ((b5 + 8) >> 4) / 10;
+        Ok(p)
+    }
+
+    //Reads the temperature in celcius and stores it
+    fn read_temperature<D: DelayMs<u32>>(&mut self, delay: &mut D) -> Result<i32, SensorError<E>> {
+        let ut = self.read_raw_temperature(delay)?;
+        let b5 = self.compute_b5(ut);
+        self.b5 = Some(b5);
+        let t = self.compensate_temperature(b5) / 10;
 
         self.data.temperature = t;
 
-        return t
+        Ok(t)
     }
-}
\ No newline at end of file
+}
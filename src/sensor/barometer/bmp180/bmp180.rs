@@ -1,64 +1,174 @@
-use stm32f4xx_hal::{i2c::{I2c, Instance as I2cInstance}, pac::TIM1, timer::DelayMs};
-use embedded_hal::prelude::_embedded_hal_blocking_delay_DelayMs; //Bring the DelayMs trait into scope
+use core::fmt::Debug;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use embedded_hal::blocking::delay::DelayMs;
 use crate::sensor::{SensorError, SensorState};
-use super::bmp180_s::{BmpData, Coeffs, RegisterMap, BMP180};
+use crate::sensor::barometer::AltitudeReference;
+use super::bmp180_s::{BmpData, Coeffs, ConversionState, Oversampling, RegisterMap, BMP180, TEMPERATURE_CONVERSION_TIME_MS};
 
-impl<'a, T  > BMP180<'a, T> where T: I2cInstance {
-    pub fn new(i2c: &'a mut I2c<T>/*, delay: &'a mut DelayMs<TIM1>*/) -> Self {
+impl<'a, I2C, E> BMP180<'a, I2C> where I2C: Write<Error = E> + WriteRead<Error = E>, E: Debug {
+    pub fn new(i2c: &'a mut I2C) -> Self {
         BMP180 {
             calib_coeffs: Coeffs {
+                ac1: 0,
+                ac2: 0,
+                ac3: 0,
+                ac4: 0,
                 ac5: 0,
                 ac6: 0,
+                b1: 0,
+                b2: 0,
+                mb: 0,
                 mc: 0,
                 md: 0
             },
             addr: 0x77,
             register_map: RegisterMap {
                 reg_id_addr: 0xD0,
+                ac1_msb_addr: 0xAA,
+                ac2_msb_addr: 0xAC,
+                ac3_msb_addr: 0xAE,
+                ac4_msb_addr: 0xB0,
                 ac5_msb_addr: 0xB2,
                 ac6_msb_addr: 0xB4,
+                b1_msb_addr: 0xB6,
+                b2_msb_addr: 0xB8,
+                mb_msb_addr: 0xBA,
                 mc_msb_addr: 0xBC,
                 md_msb_addr: 0xBE,
                 ctrl_meas_addr: 0xF4,
                 meas_out_lsb_addr: 0xF7,
-                meas_out_msb_addr: 0xF6
+                meas_out_msb_addr: 0xF6,
+                meas_out_xlsb_addr: 0xF8
             },
             i2c,
             state: SensorState::INITIAL,
-            //delay,
-            data: BmpData::new()
+            data: BmpData::new(),
+            oversampling: Oversampling::UltraLowPower,
+            altitude_reference: AltitudeReference::new(),
+            conversion_state: ConversionState::Idle,
+            b5: None
         }
     }
 
+    pub fn set_oversampling(&mut self, oversampling: Oversampling) {
+        self.oversampling = oversampling;
+    }
+
     //Sanity check to ensure the sensor is powered on and accessible
-    pub fn sanity_check(&mut self) -> bool {
+    pub fn sanity_check(&mut self) -> Result<(), SensorError<E>> {
         let mut rx_buffer: [u8; 2] = [0; 2];
-        let mut rx_word: i16;
 
         //Read the id from the sensor to confirm it is powered on and accessible
-        self.i2c.write_read(self.addr, &[self.register_map.reg_id_addr], &mut rx_buffer).unwrap();
-        return if rx_buffer[0] == 0x55 {
+        self.i2c.write_read(self.addr, &[self.register_map.reg_id_addr], &mut rx_buffer).map_err(|e| self.state.bus_error(e))?;
+        if rx_buffer[0] == 0x55 {
             // BMP180 detected
-            true
+            Ok(())
         } else {
             // BMP180 not detected
-            self.state = SensorState::ERROR(SensorError::NotFound);
-            false
+            let error = SensorError::WrongId { expected: 0x55, found: rx_buffer[0] };
+            self.state = SensorState::ERROR(error.kind());
+            Err(error)
         }
     }
 
-    pub fn read_calibration_coefficient(&mut self, addr: u8) -> Result<i16, ()> {
+    pub fn read_calibration_coefficient(&mut self, addr: u8) -> Result<i16, SensorError<E>> {
         let mut rx_buffer: [u8; 2] = [0; 2];
-        let mut rx_word: i16 = 0;
 
-        self.i2c.write_read(self.addr, &[addr], &mut rx_buffer).unwrap();
-        rx_word = ((rx_buffer[0] as i16) << 8) | rx_buffer[1] as i16;
+        self.i2c.write_read(self.addr, &[addr], &mut rx_buffer).map_err(|e| self.state.bus_error(e))?;
+        let rx_word = ((rx_buffer[0] as i16) << 8) | rx_buffer[1] as i16;
 
-        return if rx_word == 0 {
+        //0x0000 and 0xFFFF both indicate that the EEPROM could not be read (section 3.4 in the datasheet)
+        if rx_word == 0 || rx_word == -1 {
             self.state = SensorState::ERROR(SensorError::FailedToCalibrate);
-            Err(())
+            Err(SensorError::FailedToCalibrate)
         } else {
             Ok(rx_word)
         }
     }
-}
\ No newline at end of file
+
+    //Reads the uncompensated temperature value (UT)
+    pub fn read_raw_temperature<D: DelayMs<u32>>(&mut self, delay: &mut D) -> Result<i32, SensorError<E>> {
+        self.trigger_temperature_conversion()?;
+        delay.delay_ms(TEMPERATURE_CONVERSION_TIME_MS);
+        self.read_temperature_conversion()
+    }
+
+    //Reads the uncompensated pressure value (UP)
+    pub fn read_raw_pressure<D: DelayMs<u32>>(&mut self, delay: &mut D) -> Result<i32, SensorError<E>> {
+        self.trigger_pressure_conversion()?;
+        delay.delay_ms(self.oversampling.conversion_time_ms());
+        self.read_pressure_conversion()
+    }
+
+    pub(crate) fn trigger_temperature_conversion(&mut self) -> Result<(), SensorError<E>> {
+        self.i2c.write(self.addr, &[self.register_map.ctrl_meas_addr, 0x2E]).map_err(|e| self.state.bus_error(e))
+    }
+
+    pub(crate) fn trigger_pressure_conversion(&mut self) -> Result<(), SensorError<E>> {
+        let oss = self.oversampling as u8;
+        self.i2c.write(self.addr, &[self.register_map.ctrl_meas_addr, 0x34 + (oss << 6)]).map_err(|e| self.state.bus_error(e))
+    }
+
+    //Reads UT once a temperature conversion has finished
+    pub(crate) fn read_temperature_conversion(&mut self) -> Result<i32, SensorError<E>> {
+        let mut rx_buffer: [u8; 2] = [0; 2];
+        self.i2c.write_read(self.addr, &[self.register_map.meas_out_msb_addr], &mut rx_buffer).map_err(|e| self.state.bus_error(e))?;
+
+        Ok(((rx_buffer[0] as i32) << 8) | rx_buffer[1] as i32)
+    }
+
+    //Reads UP once a pressure conversion has finished
+    pub(crate) fn read_pressure_conversion(&mut self) -> Result<i32, SensorError<E>> {
+        let oss = self.oversampling as u8;
+
+        //The result is spread over the MSB, LSB and XLSB registers, which are read in one go
+        let mut rx_buffer: [u8; 3] = [0; 3];
+        self.i2c.write_read(self.addr, &[self.register_map.meas_out_msb_addr], &mut rx_buffer).map_err(|e| self.state.bus_error(e))?;
+
+        let rx_word = ((rx_buffer[0] as i32) << 16) | ((rx_buffer[1] as i32) << 8) | rx_buffer[2] as i32;
+        Ok(rx_word >> (8 - oss))
+    }
+
+    //Calculates the B5 value shared by the temperature and pressure compensation from UT
+    pub fn compute_b5(&self, ut: i32) -> i32 {
+        let x1 = ((ut - self.calib_coeffs.ac6 as i32) * (self.calib_coeffs.ac5 as i32)) >> 15;
+        let x2 = ((self.calib_coeffs.mc as i32) << 11) / (x1 + self.calib_coeffs.md as i32);
+        x1 + x2
+    }
+
+    //Calculates the true temperature in 0.1 degrees celcius from B5
+    pub fn compensate_temperature(&self, b5: i32) -> i32 {
+        (b5 + 8) >> 4
+    }
+
+    //Calculates the true pressure in Pa from UP and B5. Refer to figure 4 in the datasheet
+    pub fn compensate_pressure(&self, up: i32, b5: i32) -> i32 {
+        let oss = self.oversampling as u8;
+        let coeffs = &self.calib_coeffs;
+
+        let b6 = b5 - 4000;
+        let mut x1 = ((coeffs.b2 as i32) * ((b6 * b6) >> 12)) >> 11;
+        let mut x2 = ((coeffs.ac2 as i32) * b6) >> 11;
+        let mut x3 = x1 + x2;
+        let b3 = ((((coeffs.ac1 as i32) * 4 + x3) << oss) + 2) / 4;
+
+        x1 = ((coeffs.ac3 as i32) * b6) >> 13;
+        x2 = ((coeffs.b1 as i32) * ((b6 * b6) >> 12)) >> 16;
+        x3 = ((x1 + x2) + 2) >> 2;
+        let b4 = ((coeffs.ac4 as u32) * ((x3 + 32768) as u32)) >> 15;
+        let b7 = (up as u32).wrapping_sub(b3 as u32).wrapping_mul(50000 >> oss);
+
+        let mut p = if b7 < 0x80000000 {
+            ((b7 * 2) / b4) as i32
+        } else {
+            ((b7 / b4) * 2) as i32
+        };
+
+        x1 = (p >> 8) * (p >> 8);
+        x1 = (x1 * 3038) >> 16;
+        x2 = (-7357 * p) >> 16;
+        p += (x1 + x2 + 3791) >> 4;
+
+        p
+    }
+}
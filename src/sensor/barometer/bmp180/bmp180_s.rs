@@ -1,12 +1,7 @@
-use embedded_hal::blocking::i2c::WriteRead;
-use stm32f4xx_hal::{hal, pac::TIM1};
-use stm32f4xx_hal::i2c::{I2c, Instance as I2cInstance};
-use stm32f4xx_hal::timer::{DelayMs, Timer, TimerExt, Instance as TimerInstance};
-use stm32f4xx_hal::rcc::Clocks;
-use embedded_hal::prelude::*;
-
 use crate::sensor::SensorState;
+use crate::sensor::barometer::AltitudeReference;
 
+#[derive(Default)]
 pub struct BmpData {
     pub temperature: i32,
     pub pressure: i32
@@ -21,29 +16,89 @@ impl BmpData {
     }
 }
 
-pub struct BMP180<'a, T> where T: I2cInstance {
+pub struct BMP180<'a, I2C> {
     pub calib_coeffs: Coeffs,
     pub addr: u8,
     pub register_map: RegisterMap,
-    pub i2c: &'a mut I2c<T>, //Allows for the BMP180 struct to not take ownership of the I2C instance, which means multiple devices can be on the same bus :)
+    pub i2c: &'a mut I2C, //Any bus implementing the embedded-hal blocking I2C traits. Borrowed so that the struct does not take ownership of the I2C instance
     pub state: SensorState,
-    pub data: BmpData
+    pub data: BmpData,
+    pub oversampling: Oversampling,
+    pub altitude_reference: AltitudeReference,
+    pub conversion_state: ConversionState,
+    pub b5: Option<i32> //Intermediate value from the last temperature reading, needed to compensate pressure readings
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Conversion {
+    Temperature,
+    Pressure
+}
+
+//Tracks the conversion currently running on the sensor. started_at is in ticks of the caller's ms clock
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ConversionState {
+    Idle,
+    Converting {
+        conversion: Conversion,
+        started_at: u32
+    }
+}
+
+//Pressure oversampling setting (OSS). Higher settings take more samples per reading, trading power and conversion time for lower noise
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Oversampling {
+    UltraLowPower = 0,
+    Standard = 1,
+    HighResolution = 2,
+    UltraHighResolution = 3
+}
+
+//Maximum temperature conversion time in ms, rounded up. Refer to table 3 in the datasheet
+pub const TEMPERATURE_CONVERSION_TIME_MS: u32 = 5;
+
+impl Oversampling {
+    //Maximum pressure conversion time in ms, rounded up. Refer to table 3 in the datasheet
+    pub fn conversion_time_ms(&self) -> u32 {
+        match self {
+            Oversampling::UltraLowPower => 5,
+            Oversampling::Standard => 8,
+            Oversampling::HighResolution => 14,
+            Oversampling::UltraHighResolution => 26
+        }
+    }
 }
 
 pub struct RegisterMap {
     pub reg_id_addr: u8,
+    pub ac1_msb_addr: u8,
+    pub ac2_msb_addr: u8,
+    pub ac3_msb_addr: u8,
+    pub ac4_msb_addr: u8,
     pub ac5_msb_addr: u8,
     pub ac6_msb_addr: u8,
+    pub b1_msb_addr: u8,
+    pub b2_msb_addr: u8,
+    pub mb_msb_addr: u8,
     pub mc_msb_addr: u8,
     pub md_msb_addr: u8,
     pub ctrl_meas_addr: u8,
     pub meas_out_lsb_addr: u8,
-    pub meas_out_msb_addr: u8
+    pub meas_out_msb_addr: u8,
+    pub meas_out_xlsb_addr: u8
 }
 
+//Calibration coefficients stored in the sensor's EEPROM. AC4, AC5 and AC6 are unsigned, the rest are signed (table 5 in the datasheet)
 pub struct Coeffs {
-    pub ac5: i16,
-    pub ac6: i16,
+    pub ac1: i16,
+    pub ac2: i16,
+    pub ac3: i16,
+    pub ac4: u16,
+    pub ac5: u16,
+    pub ac6: u16,
+    pub b1: i16,
+    pub b2: i16,
+    pub mb: i16,
     pub mc: i16,
     pub md: i16
-}
\ No newline at end of file
+}
@@ -0,0 +1,80 @@
+use core::fmt::Debug;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use crate::sensor::SensorError;
+use super::bmp180_s::{Conversion, ConversionState, BMP180, TEMPERATURE_CONVERSION_TIME_MS};
+
+/*
+ * Non-blocking alternative to Barometer::read_temperature and Barometer::read_pressure.
+ * A conversion is started with start_conversion, and poll_conversion is then called from the main loop
+ * until the conversion time has elapsed. Both take the current time from a ms tick supplied by the caller.
+ */
+impl<'a, I2C, E> BMP180<'a, I2C> where I2C: Write<Error = E> + WriteRead<Error = E>, E: Debug {
+    //Starts a conversion and returns the conversion that was actually started. Pressure readings can only be
+    //compensated once a temperature reading exists, so a temperature conversion is started instead if there is none yet.
+    //Returns SensorError::Busy while another conversion is running, as starting a new one would discard its result
+    pub fn start_conversion(&mut self, conversion: Conversion, now: u32) -> Result<Conversion, SensorError<E>> {
+        if self.is_converting() {
+            return Err(SensorError::Busy);
+        }
+
+        let conversion = match conversion {
+            Conversion::Pressure if self.b5.is_none() => Conversion::Temperature,
+            conversion => conversion
+        };
+
+        match conversion {
+            Conversion::Temperature => self.trigger_temperature_conversion()?,
+            Conversion::Pressure => self.trigger_pressure_conversion()?
+        }
+
+        self.conversion_state = ConversionState::Converting {
+            conversion,
+            started_at: now
+        };
+
+        Ok(conversion)
+    }
+
+    //Checks whether the running conversion has finished. If it has, the result is read, compensated and stored,
+    //and the finished conversion is returned
+    pub fn poll_conversion(&mut self, now: u32) -> Result<Option<Conversion>, SensorError<E>> {
+        let (conversion, started_at) = match self.conversion_state {
+            ConversionState::Idle => return Ok(None),
+            ConversionState::Converting { conversion, started_at } => (conversion, started_at)
+        };
+
+        let conversion_time = match conversion {
+            Conversion::Temperature => TEMPERATURE_CONVERSION_TIME_MS,
+            Conversion::Pressure => self.oversampling.conversion_time_ms()
+        };
+
+        //wrapping_sub keeps the elapsed time correct when the tick counter overflows
+        if now.wrapping_sub(started_at) < conversion_time {
+            return Ok(None);
+        }
+
+        //The conversion is over either way, even if reading its result fails
+        self.conversion_state = ConversionState::Idle;
+
+        match conversion {
+            Conversion::Temperature => {
+                let ut = self.read_temperature_conversion()?;
+                let b5 = self.compute_b5(ut);
+                self.b5 = Some(b5);
+                self.data.temperature = self.compensate_temperature(b5) / 10;
+            },
+            Conversion::Pressure => {
+                let up = self.read_pressure_conversion()?;
+                if let Some(b5) = self.b5 {
+                    self.data.pressure = self.compensate_pressure(up, b5);
+                }
+            }
+        }
+
+        Ok(Some(conversion))
+    }
+
+    pub fn is_converting(&self) -> bool {
+        self.conversion_state != ConversionState::Idle
+    }
+}
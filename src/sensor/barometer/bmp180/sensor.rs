@@ -1,24 +1,31 @@
 use crate::sensor::barometer::bmp180::bmp180_s::BMP180;
 use crate::sensor::Sensor;
-use crate::sensor::SensorState;
-use stm32f4xx_hal::i2c::{I2c, Instance as I2cInstance};
+use crate::sensor::{SensorError, SensorState};
+use core::fmt::Debug;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
 
-impl <'a, T> Sensor for BMP180<'a, T> where T: I2cInstance {
-    fn init(&mut self) -> Result<(), ()> {
+impl<'a, I2C, E> Sensor for BMP180<'a, I2C> where I2C: Write<Error = E> + WriteRead<Error = E>, E: Debug {
+    type BusError = E;
+
+    fn init(&mut self) -> Result<(), SensorError<E>> {
         self.state = SensorState::STARTUP;
-        let is_discovered = self.sanity_check();
-        if !is_discovered {
-            return Err(());
-        }
+        self.sanity_check()?;
 
         self.calibrate()
     }
 
-    fn calibrate(&mut self) -> Result<(), ()> {
+    fn calibrate(&mut self) -> Result<(), SensorError<E>> {
         self.state = SensorState::CALIBRATING;
 
-        self.calib_coeffs.ac5 = self.read_calibration_coefficient(self.register_map.ac5_msb_addr)?;
-        self.calib_coeffs.ac6 = self.read_calibration_coefficient(self.register_map.ac6_msb_addr)?;
+        self.calib_coeffs.ac1 = self.read_calibration_coefficient(self.register_map.ac1_msb_addr)?;
+        self.calib_coeffs.ac2 = self.read_calibration_coefficient(self.register_map.ac2_msb_addr)?;
+        self.calib_coeffs.ac3 = self.read_calibration_coefficient(self.register_map.ac3_msb_addr)?;
+        self.calib_coeffs.ac4 = self.read_calibration_coefficient(self.register_map.ac4_msb_addr)? as u16;
+        self.calib_coeffs.ac5 = self.read_calibration_coefficient(self.register_map.ac5_msb_addr)? as u16;
+        self.calib_coeffs.ac6 = self.read_calibration_coefficient(self.register_map.ac6_msb_addr)? as u16;
+        self.calib_coeffs.b1 = self.read_calibration_coefficient(self.register_map.b1_msb_addr)?;
+        self.calib_coeffs.b2 = self.read_calibration_coefficient(self.register_map.b2_msb_addr)?;
+        self.calib_coeffs.mb = self.read_calibration_coefficient(self.register_map.mb_msb_addr)?;
         self.calib_coeffs.mc = self.read_calibration_coefficient(self.register_map.mc_msb_addr)?;
         self.calib_coeffs.md = self.read_calibration_coefficient(self.register_map.md_msb_addr)?;
 
@@ -28,6 +35,6 @@ impl <'a, T> Sensor for BMP180<'a, T> where T: I2cInstance {
     }
 
     fn sensor_state(&self) -> &SensorState {
-        return &self.state;
+        &self.state
     }
 }
\ No newline at end of file
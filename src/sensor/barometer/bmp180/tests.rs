@@ -0,0 +1,251 @@
+use crate::mock::{MockDelay, MockError, MockI2c};
+use crate::sensor::barometer::Barometer;
+use crate::sensor::calibration::CalibrationError;
+use crate::sensor::{Sensor, SensorError, SensorState};
+use super::bmp180_s::{Conversion, Oversampling, BMP180};
+
+const ADDR: u8 = 0x77;
+
+//Calibration coefficients from the worked example in the datasheet (section 3.5)
+const DATASHEET_CALIBRATION: [u8; 22] = [
+    0x01, 0x98, //AC1 = 408
+    0xFF, 0xB8, //AC2 = -72
+    0xC7, 0xD1, //AC3 = -14383
+    0x7F, 0xE5, //AC4 = 32741
+    0x7F, 0xF5, //AC5 = 32757
+    0x5A, 0x71, //AC6 = 23153
+    0x18, 0x2E, //B1 = 6190
+    0x00, 0x04, //B2 = 4
+    0x80, 0x00, //MB = -32768
+    0xDD, 0xF9, //MC = -8711
+    0x0B, 0x34 //MD = 2868
+];
+const DATASHEET_UT: [u8; 2] = [0x6C, 0xFA]; //27898
+const DATASHEET_UP: [u8; 3] = [0x5D, 0x23, 0x00]; //23843 with OSS = 0
+
+fn datasheet_bus() -> MockI2c {
+    let mut i2c = MockI2c::new();
+    i2c.set_register(ADDR, 0xD0, 0x55);
+    i2c.set_registers(ADDR, 0xAA, &DATASHEET_CALIBRATION);
+    i2c
+}
+
+#[test]
+fn sanity_check_accepts_chip_id() {
+    let mut i2c = datasheet_bus();
+    let mut bmp180 = BMP180::new(&mut i2c);
+    assert!(bmp180.sanity_check().is_ok());
+}
+
+#[test]
+fn sanity_check_rejects_wrong_chip_id() {
+    let mut i2c = datasheet_bus();
+    i2c.set_register(ADDR, 0xD0, 0x58);
+    let mut bmp180 = BMP180::new(&mut i2c);
+    assert_eq!(bmp180.sanity_check(), Err(SensorError::WrongId { expected: 0x55, found: 0x58 }));
+    assert_eq!(bmp180.sensor_state(), &SensorState::ERROR(SensorError::WrongId { expected: 0x55, found: 0x58 }));
+}
+
+#[test]
+fn init_reads_all_calibration_coefficients() {
+    let mut i2c = datasheet_bus();
+    let mut bmp180 = BMP180::new(&mut i2c);
+    assert!(bmp180.init().is_ok());
+    assert!(matches!(bmp180.sensor_state(), SensorState::READY));
+
+    let coeffs = &bmp180.calib_coeffs;
+    assert_eq!(coeffs.ac1, 408);
+    assert_eq!(coeffs.ac2, -72);
+    assert_eq!(coeffs.ac3, -14383);
+    assert_eq!(coeffs.ac4, 32741);
+    assert_eq!(coeffs.ac5, 32757);
+    assert_eq!(coeffs.ac6, 23153);
+    assert_eq!(coeffs.b1, 6190);
+    assert_eq!(coeffs.b2, 4);
+    assert_eq!(coeffs.mb, -32768);
+    assert_eq!(coeffs.mc, -8711);
+    assert_eq!(coeffs.md, 2868);
+}
+
+#[test]
+fn calibrate_fails_on_blank_eeprom() {
+    let mut i2c = datasheet_bus();
+    i2c.set_registers(ADDR, 0xAA, &[0xFF, 0xFF]);
+    let mut bmp180 = BMP180::new(&mut i2c);
+    assert_eq!(bmp180.calibrate(), Err(SensorError::FailedToCalibrate));
+}
+
+#[test]
+fn bus_errors_are_returned() {
+    let mut i2c = datasheet_bus();
+    i2c.nack = true;
+    let mut delay = MockDelay::default();
+
+    let mut bmp180 = BMP180::new(&mut i2c);
+    assert_eq!(bmp180.init(), Err(SensorError::Bus(MockError::Nack)));
+    assert_eq!(bmp180.read_pressure(&mut delay), Err(SensorError::Bus(MockError::Nack)));
+    assert_eq!(bmp180.sensor_state(), &SensorState::ERROR(SensorError::Bus(())));
+}
+
+#[test]
+fn reinit_recovers_from_error() {
+    let mut i2c = datasheet_bus();
+    i2c.nack = true;
+    let mut bmp180 = BMP180::new(&mut i2c);
+    assert!(bmp180.init().is_err());
+    bmp180.i2c.nack = false;
+    assert!(bmp180.init().is_ok());
+    assert_eq!(bmp180.sensor_state(), &SensorState::READY);
+}
+
+#[test]
+fn datasheet_temperature() {
+    let mut i2c = datasheet_bus();
+    i2c.script_read(ADDR, 0xF6, &DATASHEET_UT);
+    let mut delay = MockDelay::default();
+
+    let mut bmp180 = BMP180::new(&mut i2c);
+    bmp180.init().ok();
+    assert_eq!(bmp180.read_temperature(&mut delay), Ok(15));
+    assert_eq!(bmp180.temperature(), 15);
+    assert_eq!(bmp180.compensate_temperature(bmp180.compute_b5(27898)), 150);
+    drop(bmp180);
+
+    assert_eq!(i2c.register_writes(ADDR), [(0xF4, vec![0x2E])]);
+    assert_eq!(delay.elapsed_ms, 5);
+}
+
+#[test]
+fn datasheet_pressure() {
+    let mut i2c = datasheet_bus();
+    i2c.script_read(ADDR, 0xF6, &DATASHEET_UT);
+    i2c.script_read(ADDR, 0xF6, &DATASHEET_UP);
+    let mut delay = MockDelay::default();
+
+    let mut bmp180 = BMP180::new(&mut i2c);
+    bmp180.init().ok();
+    assert_eq!(bmp180.read_pressure(&mut delay), Ok(69964));
+    assert_eq!(bmp180.pressure(), 69964);
+    assert_eq!(bmp180.temperature(), 15);
+    drop(bmp180);
+
+    assert_eq!(i2c.register_writes(ADDR), [(0xF4, vec![0x2E]), (0xF4, vec![0x34])]);
+}
+
+#[test]
+fn oversampling_sets_control_value_and_wait() {
+    let mut i2c = datasheet_bus();
+    let mut delay = MockDelay::default();
+
+    let mut bmp180 = BMP180::new(&mut i2c);
+    bmp180.set_oversampling(Oversampling::UltraHighResolution);
+    bmp180.read_raw_pressure(&mut delay).ok();
+    drop(bmp180);
+
+    assert_eq!(i2c.register_writes(ADDR), [(0xF4, vec![0xF4])]);
+    assert_eq!(delay.elapsed_ms, 26);
+}
+
+#[test]
+fn raw_pressure_is_shifted_by_oversampling() {
+    let mut i2c = datasheet_bus();
+    i2c.script_read(ADDR, 0xF6, &[0x5D, 0x23, 0xC0]);
+    let mut delay = MockDelay::default();
+
+    let mut bmp180 = BMP180::new(&mut i2c);
+    bmp180.set_oversampling(Oversampling::HighResolution);
+    assert_eq!(bmp180.read_raw_pressure(&mut delay), Ok(0x5D23C0 >> 6));
+}
+
+#[test]
+fn non_blocking_conversion_waits_for_conversion_time() {
+    let mut i2c = datasheet_bus();
+    i2c.script_read(ADDR, 0xF6, &DATASHEET_UT);
+    i2c.script_read(ADDR, 0xF6, &DATASHEET_UP);
+
+    let mut bmp180 = BMP180::new(&mut i2c);
+    bmp180.init().ok();
+
+    //Pressure cannot be compensated before a temperature reading exists
+    assert_eq!(bmp180.start_conversion(Conversion::Pressure, 100), Ok(Conversion::Temperature));
+    assert!(bmp180.is_converting());
+    assert_eq!(bmp180.poll_conversion(104), Ok(None));
+    assert_eq!(bmp180.poll_conversion(105), Ok(Some(Conversion::Temperature)));
+    assert_eq!(bmp180.temperature(), 15);
+
+    assert_eq!(bmp180.start_conversion(Conversion::Pressure, u32::MAX - 1), Ok(Conversion::Pressure));
+    assert_eq!(bmp180.poll_conversion(2), Ok(None));
+    assert_eq!(bmp180.poll_conversion(3), Ok(Some(Conversion::Pressure)));
+    assert_eq!(bmp180.pressure(), 69964);
+    assert!(!bmp180.is_converting());
+}
+
+#[test]
+fn start_conversion_rejects_a_running_conversion() {
+    let mut i2c = datasheet_bus();
+    i2c.script_read(ADDR, 0xF6, &DATASHEET_UT);
+
+    let mut bmp180 = BMP180::new(&mut i2c);
+    bmp180.init().ok();
+
+    assert_eq!(bmp180.start_conversion(Conversion::Temperature, 100), Ok(Conversion::Temperature));
+    assert_eq!(bmp180.start_conversion(Conversion::Temperature, 103), Err(SensorError::Busy));
+
+    //The running conversion keeps its start time
+    assert_eq!(bmp180.poll_conversion(105), Ok(Some(Conversion::Temperature)));
+    assert_eq!(bmp180.temperature(), 15);
+    drop(bmp180);
+
+    assert_eq!(i2c.register_writes(ADDR), [(0xF4, vec![0x2E])]);
+}
+
+#[test]
+fn altitude_from_pressure() {
+    let mut i2c = datasheet_bus();
+    let mut bmp180 = BMP180::new(&mut i2c);
+
+    bmp180.data.pressure = 101325;
+    assert!(bmp180.altitude().abs() < 1.0);
+
+    //The standard atmosphere has a pressure of roughly 89875 Pa at 1000 m
+    bmp180.data.pressure = 89875;
+    assert!((bmp180.altitude() - 1000.0).abs() < 10.0);
+
+    assert!(bmp180.zero_altitude().is_ok());
+    assert!(bmp180.relative_altitude().abs() < 0.01);
+
+    bmp180.calibrate_sea_level_pressure(1000.0);
+    assert!((bmp180.sea_level_pressure() - 101325.0).abs() < 1000.0);
+}
+
+#[test]
+fn zero_altitude_requires_a_pressure_reading() {
+    let mut i2c = datasheet_bus();
+    let mut bmp180 = BMP180::new(&mut i2c);
+
+    assert_eq!(bmp180.zero_altitude(), Err(SensorError::InvalidConfiguration));
+    assert_eq!(bmp180.altitude_reference.ground_pressure, None);
+    assert!(bmp180.relative_altitude().is_finite());
+}
+
+#[test]
+fn altitude_reference_is_restored_from_record() {
+    let mut i2c = datasheet_bus();
+    let mut bmp180 = BMP180::new(&mut i2c);
+    bmp180.data.pressure = 89875;
+    assert!(bmp180.zero_altitude().is_ok());
+    bmp180.set_sea_level_pressure(101000.0);
+
+    let mut record = [0u8; 32];
+    let len = bmp180.store_altitude_reference(&mut record).unwrap();
+    drop(bmp180);
+
+    let mut i2c = datasheet_bus();
+    let mut restored = BMP180::new(&mut i2c);
+    assert!(restored.load_altitude_reference(&record[..len]).is_ok());
+    assert_eq!(restored.altitude_reference.ground_pressure, Some(89875.0));
+    assert_eq!(restored.sea_level_pressure(), 101000.0);
+
+    record[6] ^= 0xFF;
+    assert_eq!(restored.load_altitude_reference(&record[..len]), Err(CalibrationError::Corrupted));
+}
@@ -0,0 +1,231 @@
+/*
+ * Binary encoding for calibration data, so it can be persisted to flash or sent over serial and reapplied at boot.
+ *
+ * Each value is stored as a record:
+ * | kind (u8) | version (u8) | payload length (u16) | payload | CRC-32 of everything before it (u32) |
+ * All fields are little-endian. The kind identifies the type of the payload, and the version is bumped whenever its
+ * layout changes, so that data written by older firmware is rejected rather than misread.
+ */
+use byteorder::{ByteOrder, LittleEndian};
+
+pub const RECORD_HEADER_LEN: usize = 4;
+pub const RECORD_CRC_LEN: usize = 4;
+
+//Record kinds. Each type stored in a record needs its own kind
+pub const KIND_LSM9DS1_CALIBRATION: u8 = 0x01;
+pub const KIND_ALTITUDE_REFERENCE: u8 = 0x02;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CalibrationError {
+    BufferTooSmall,
+    WrongKind {
+        expected: u8,
+        found: u8
+    },
+    UnsupportedVersion {
+        expected: u8,
+        found: u8
+    },
+    Corrupted //The CRC or the payload length does not match
+}
+
+//Implemented by calibration data which can be stored in a record
+pub trait Persistent: Sized {
+    const KIND: u8;
+    const VERSION: u8;
+    const PAYLOAD_LEN: usize;
+
+    fn encode_payload(&self, payload: &mut PayloadWriter);
+    fn decode_payload(payload: &mut PayloadReader) -> Self;
+
+    //Size of the encoded record in bytes
+    fn record_len() -> usize {
+        RECORD_HEADER_LEN + Self::PAYLOAD_LEN + RECORD_CRC_LEN
+    }
+
+    //Encodes the value into the start of buffer, returning the number of bytes used
+    fn encode(&self, buffer: &mut [u8]) -> Result<usize, CalibrationError> {
+        let len = Self::record_len();
+        if buffer.len() < len {
+            return Err(CalibrationError::BufferTooSmall);
+        }
+
+        buffer[0] = Self::KIND;
+        buffer[1] = Self::VERSION;
+        LittleEndian::write_u16(&mut buffer[2..4], Self::PAYLOAD_LEN as u16);
+        self.encode_payload(&mut PayloadWriter::new(&mut buffer[RECORD_HEADER_LEN..len - RECORD_CRC_LEN]));
+
+        let crc = crc32(&buffer[..len - RECORD_CRC_LEN]);
+        LittleEndian::write_u32(&mut buffer[len - RECORD_CRC_LEN..len], crc);
+
+        Ok(len)
+    }
+
+    //Decodes a record from the start of buffer, checking its kind, version and CRC
+    fn decode(buffer: &[u8]) -> Result<Self, CalibrationError> {
+        if buffer.len() < RECORD_HEADER_LEN {
+            return Err(CalibrationError::BufferTooSmall);
+        }
+        if buffer[0] != Self::KIND {
+            return Err(CalibrationError::WrongKind { expected: Self::KIND, found: buffer[0] });
+        }
+        if buffer[1] != Self::VERSION {
+            return Err(CalibrationError::UnsupportedVersion { expected: Self::VERSION, found: buffer[1] });
+        }
+        if LittleEndian::read_u16(&buffer[2..4]) as usize != Self::PAYLOAD_LEN {
+            return Err(CalibrationError::Corrupted);
+        }
+
+        let len = Self::record_len();
+        if buffer.len() < len {
+            return Err(CalibrationError::BufferTooSmall);
+        }
+        if crc32(&buffer[..len - RECORD_CRC_LEN]) != LittleEndian::read_u32(&buffer[len - RECORD_CRC_LEN..len]) {
+            return Err(CalibrationError::Corrupted);
+        }
+
+        Ok(Self::decode_payload(&mut PayloadReader::new(&buffer[RECORD_HEADER_LEN..len - RECORD_CRC_LEN])))
+    }
+}
+
+//Writes little-endian values one after the other. Payloads are sized by Persistent::PAYLOAD_LEN, so writes never overflow
+pub struct PayloadWriter<'a> {
+    buffer: &'a mut [u8],
+    position: usize
+}
+
+impl<'a> PayloadWriter<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        PayloadWriter {
+            buffer,
+            position: 0
+        }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.buffer[self.position] = value;
+        self.position += 1;
+    }
+
+    pub fn write_i32(&mut self, value: i32) {
+        LittleEndian::write_i32(&mut self.buffer[self.position..], value);
+        self.position += 4;
+    }
+
+    pub fn write_f32(&mut self, value: f32) {
+        LittleEndian::write_f32(&mut self.buffer[self.position..], value);
+        self.position += 4;
+    }
+}
+
+pub struct PayloadReader<'a> {
+    buffer: &'a [u8],
+    position: usize
+}
+
+impl<'a> PayloadReader<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        PayloadReader {
+            buffer,
+            position: 0
+        }
+    }
+
+    pub fn read_u8(&mut self) -> u8 {
+        let value = self.buffer[self.position];
+        self.position += 1;
+        value
+    }
+
+    pub fn read_i32(&mut self) -> i32 {
+        let value = LittleEndian::read_i32(&self.buffer[self.position..]);
+        self.position += 4;
+        value
+    }
+
+    pub fn read_f32(&mut self) -> f32 {
+        let value = LittleEndian::read_f32(&self.buffer[self.position..]);
+        self.position += 4;
+        value
+    }
+}
+
+//CRC-32 (IEEE 802.3), computed bitwise to avoid a lookup table in flash
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Pair {
+        a: i32,
+        b: f32
+    }
+
+    impl Persistent for Pair {
+        const KIND: u8 = 0x7F;
+        const VERSION: u8 = 2;
+        const PAYLOAD_LEN: usize = 8;
+
+        fn encode_payload(&self, payload: &mut PayloadWriter) {
+            payload.write_i32(self.a);
+            payload.write_f32(self.b);
+        }
+
+        fn decode_payload(payload: &mut PayloadReader) -> Self {
+            Pair {
+                a: payload.read_i32(),
+                b: payload.read_f32()
+            }
+        }
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let mut buffer = [0u8; 32];
+        assert_eq!(Pair { a: -5, b: 1.5 }.encode(&mut buffer), Ok(16));
+        assert_eq!(&buffer[..4], &[0x7F, 2, 8, 0]);
+
+        let pair = Pair::decode(&buffer).unwrap();
+        assert_eq!((pair.a, pair.b), (-5, 1.5));
+    }
+
+    #[test]
+    fn record_rejects_corruption() {
+        let mut buffer = [0u8; 16];
+        Pair { a: -5, b: 1.5 }.encode(&mut buffer).unwrap();
+
+        buffer[5] ^= 0x01;
+        assert!(matches!(Pair::decode(&buffer), Err(CalibrationError::Corrupted)));
+    }
+
+    #[test]
+    fn record_checks_header() {
+        let mut buffer = [0u8; 16];
+        assert!(matches!(Pair { a: 0, b: 0.0 }.encode(&mut buffer[..15]), Err(CalibrationError::BufferTooSmall)));
+        Pair { a: 0, b: 0.0 }.encode(&mut buffer).unwrap();
+
+        assert!(matches!(Pair::decode(&buffer[..15]), Err(CalibrationError::BufferTooSmall)));
+
+        buffer[1] = 1;
+        assert!(matches!(Pair::decode(&buffer), Err(CalibrationError::UnsupportedVersion { expected: 2, found: 1 })));
+
+        buffer[0] = 0x01;
+        assert!(matches!(Pair::decode(&buffer), Err(CalibrationError::WrongKind { expected: 0x7F, found: 0x01 })));
+    }
+}
@@ -1,17 +1,16 @@
 pub mod lsm9ds1;
 use embedded_hal::blocking::delay::DelayMs;
-use stm32f4xx_hal::{pac::TIM1, timer::Delay};
 
-use crate::sensor::Sensor;
+use crate::sensor::{Sensor, SensorError};
 
 pub trait Accelerometer: Sensor {
-    fn read_acceleration(&mut self) -> (f32, f32, f32);
+    fn read_acceleration(&mut self) -> Result<(f32, f32, f32), SensorError<Self::BusError>>;
 }
 
 pub trait Gyroscope: Sensor {
-    fn read_gyro(&mut self) -> (f32, f32, f32);
+    fn read_gyro(&mut self) -> Result<(f32, f32, f32), SensorError<Self::BusError>>;
 }
 
 pub trait Magnetometer: Sensor {
-    fn read_magnetometer(&mut self) -> (i32, i32, i32);
-}
\ No newline at end of file
+    fn read_magnetometer(&mut self) -> Result<(f32, f32, f32), SensorError<Self::BusError>>;
+}
@@ -1,6 +1,16 @@
+#[allow(clippy::module_inception)]
 pub mod lsm9ds1;
 pub mod lsm9ds1_s;
+pub mod interface;
 pub mod sensor;
 mod accelerometer;
+mod calibration;
+mod events;
+mod fifo;
+mod interrupts;
 mod gyroscope;
-mod magnetometer;
\ No newline at end of file
+mod magnetometer;
+mod sample;
+mod temperature;
+#[cfg(test)]
+mod tests;
@@ -1,50 +1,159 @@
+use crate::sensor::{SensorError, SensorState};
 use crate::sensor::imu::Accelerometer;
-use cortex_m::asm::nop;
-use stm32f4xx_hal::i2c::Instance as I2cInstance;
-use super::lsm9ds1_s::{AccelerometerRM, XlOdr, LSM9DS1};
+use core::fmt::Debug;
+use super::interface::{Device, RegisterInterface};
+use super::lsm9ds1_s::{AccelerometerCalibration, AccelerometerCalibrationSession, AccelerometerConfig, AccelerometerPosition, AccelerometerRM, LSM9DS1, STATUS_XLDA};
 
-impl<'a, T  > LSM9DS1<'a, T> where T: I2cInstance {
-    pub fn boot_accelerometer(&mut self) -> Result<(), ()> {
-        let odr = XlOdr::CONTINUOUS;
-        let reg_value = (odr as u8) << 5;
+impl AccelerometerCalibrationSession {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<IF, E> LSM9DS1<IF> where IF: RegisterInterface<Error = E>, E: Debug {
+    pub fn boot_accelerometer(&mut self) -> Result<(), SensorError<E>> {
+        self.configure_accelerometer(self.accelerometer_config)
+    }
+
+    //Writes the configuration to CTRL_REG6_XL and CTRL_REG7_XL. Readings are scaled using the new full scale from then on
+    pub fn configure_accelerometer(&mut self, config: AccelerometerConfig) -> Result<(), SensorError<E>> {
+        let reg6_value = config.ctrl_reg6_xl();
+        let reg7_value = config.ctrl_reg7_xl();
 
-        let initial_value = self.read_ctrl_reg6_xl();
-        let res = self.i2c.write(self.addr, &[AccelerometerRM::CtrlReg6Xl as u8, reg_value]);
-        let final_value = self.read_ctrl_reg6_xl();
+        self.interface.write_registers(Device::AccelGyro, AccelerometerRM::CtrlReg6Xl as u8, &[reg6_value]).map_err(|e| self.state.bus_error(e))?;
+        self.interface.write_registers(Device::AccelGyro, AccelerometerRM::CtrlReg7Xl as u8, &[reg7_value]).map_err(|e| self.state.bus_error(e))?;
+        let final_value = self.read_ctrl_reg6_xl()?;
 
-        if initial_value != final_value && final_value == reg_value {
+        if final_value == reg6_value {
+            self.accelerometer_config = config;
             Ok(())
         } else {
-            Err(())
+            self.state = SensorState::ERROR(SensorError::InvalidConfiguration);
+            Err(SensorError::InvalidConfiguration)
         }
     }
     
-    pub fn calibrate_accelerometer(&mut self) {
-        //todo
-        //todo!();
+    /*
+     * Records one position of the six-position calibration. The board must be held still with the given axis pointing
+     * up (or down), and the mean of that axis over the samples is stored in the session.
+     * Fails if the axis does not read at least 0.5 g in the expected direction, as the board is in the wrong orientation.
+     */
+    pub fn sample_accelerometer_position(&mut self, session: &mut AccelerometerCalibrationSession, position: AccelerometerPosition, samples: u32) -> Result<f32, SensorError<E>> {
+        let mean = self.average_acceleration(samples)?;
+        let (reading, expected) = match position {
+            AccelerometerPosition::XUp => (mean[0], 1.0),
+            AccelerometerPosition::XDown => (mean[0], -1.0),
+            AccelerometerPosition::YUp => (mean[1], 1.0),
+            AccelerometerPosition::YDown => (mean[1], -1.0),
+            AccelerometerPosition::ZUp => (mean[2], 1.0),
+            AccelerometerPosition::ZDown => (mean[2], -1.0)
+        };
+
+        if reading * expected < 0.5 {
+            self.state = SensorState::ERROR(SensorError::FailedToCalibrate);
+            return Err(SensorError::FailedToCalibrate);
+        }
+
+        session.readings[position as usize] = Some(reading);
+        Ok(reading)
     }
-    
-    pub fn read_ctrl_reg6_xl (&mut self) -> u8 {
-        let mut rx_dat: [u8; 1] = [0; 1];
-        let res = self.i2c.write_read(self.addr, &[AccelerometerRM::CtrlReg6Xl as u8], &mut rx_dat);
-        rx_dat[0]
+
+    //Computes the per-axis offset and scale once all six positions are recorded, and stores them in calibration_info
+    pub fn calibrate_accelerometer(&mut self, session: &AccelerometerCalibrationSession) -> Result<AccelerometerCalibration, SensorError<E>> {
+        let mut offsets = [0f32; 3];
+        let mut scales = [0f32; 3];
+        for axis in 0..3 {
+            match (session.readings[2 * axis], session.readings[2 * axis + 1]) {
+                (Some(up), Some(down)) if up > down => {
+                    offsets[axis] = (up + down) / 2.0;
+                    scales[axis] = 2.0 / (up - down);
+                },
+                _ => {
+                    self.state = SensorState::ERROR(SensorError::FailedToCalibrate);
+                    return Err(SensorError::FailedToCalibrate);
+                }
+            }
+        }
+
+        let calibration = AccelerometerCalibration {
+            x_offset: offsets[0],
+            y_offset: offsets[1],
+            z_offset: offsets[2],
+            x_scale: scales[0],
+            y_scale: scales[1],
+            z_scale: scales[2]
+        };
+        self.calibration_info.accelerometer = calibration;
+        Ok(calibration)
     }
-}
 
-impl<'a, T> Accelerometer for LSM9DS1<'a, T> where T: I2cInstance {
-    fn read_acceleration(&mut self) -> (f32, f32, f32) {
-        let g_range = 2; //2g max reading
+    /*
+     * Quick alternative to the six-position calibration: with the board level and the Z axis up, sets the offsets so that
+     * the board reads (0, 0, 1) g. The scale factors are kept.
+     */
+    pub fn level_accelerometer(&mut self, samples: u32) -> Result<AccelerometerCalibration, SensorError<E>> {
+        let mean = self.average_acceleration(samples)?;
 
-        let mut rx_buffer: [u8; 6] = [0; 6];
-        let res = self.i2c.write_read(self.addr, &[AccelerometerRM::OutXXlL as u8], &mut rx_buffer);
-        let x_raw = self.twos_complement(rx_buffer[1], rx_buffer[0]);
-        let y_raw = self.twos_complement(rx_buffer[3], rx_buffer[2]);
-        let z_raw = self.twos_complement(rx_buffer[5], rx_buffer[4]);
-        
-        let x = ((x_raw as i32 * g_range as i32) as f64 / i16::max_value() as f64) as f32;
-        let y = ((y_raw as i32 * g_range as i32) as f64 / i16::max_value() as f64) as f32;
-        let z = ((z_raw as i32 * g_range as i32) as f64 / i16::max_value() as f64) as f32;
+        let mut calibration = self.calibration_info.accelerometer;
+        calibration.x_offset = mean[0];
+        calibration.y_offset = mean[1];
+        calibration.z_offset = mean[2] - 1.0 / calibration.z_scale;
+        self.calibration_info.accelerometer = calibration;
+        Ok(calibration)
+    }
+
+    //Mean of the uncorrected acceleration in g, waiting for a new sample each time
+    fn average_acceleration(&mut self, samples: u32) -> Result<[f32; 3], SensorError<E>> {
+        if samples == 0 {
+            return Err(SensorError::InvalidConfiguration);
+        }
+
+        let mut sums = [0i64; 3];
+        for _ in 0..samples {
+            self.wait_for_data(STATUS_XLDA)?;
+            let (x, y, z) = self.read_raw_acceleration()?;
+            sums[0] += x as i64;
+            sums[1] += y as i64;
+            sums[2] += z as i64;
+        }
+
+        let sensitivity = self.accelerometer_config.full_scale.sensitivity();
+        Ok(sums.map(|sum| sum as f32 / samples as f32 * sensitivity))
+    }
+
+    //Converts a raw reading to g, and applies the calibration from calibration_info
+    pub(crate) fn scale_acceleration(&self, raw: (i16, i16, i16)) -> (f32, f32, f32) {
+        let sensitivity = self.accelerometer_config.full_scale.sensitivity();
+        let calibration = self.calibration_info.accelerometer;
+
+        let x = (raw.0 as f32 * sensitivity - calibration.x_offset) * calibration.x_scale;
+        let y = (raw.1 as f32 * sensitivity - calibration.y_offset) * calibration.y_scale;
+        let z = (raw.2 as f32 * sensitivity - calibration.z_offset) * calibration.z_scale;
 
         (x, y, z)
     }
-}
\ No newline at end of file
+
+    pub fn read_raw_acceleration(&mut self) -> Result<(i16, i16, i16), SensorError<E>> {
+        let mut rx_buffer: [u8; 6] = [0; 6];
+        self.interface.read_registers(Device::AccelGyro, AccelerometerRM::OutXXlL as u8, &mut rx_buffer).map_err(|e| self.state.bus_error(e))?;
+        let x = self.twos_complement(rx_buffer[1], rx_buffer[0]);
+        let y = self.twos_complement(rx_buffer[3], rx_buffer[2]);
+        let z = self.twos_complement(rx_buffer[5], rx_buffer[4]);
+
+        Ok((x, y, z))
+    }
+    
+    pub fn read_ctrl_reg6_xl (&mut self) -> Result<u8, SensorError<E>> {
+        let mut rx_dat: [u8; 1] = [0; 1];
+        self.interface.read_registers(Device::AccelGyro, AccelerometerRM::CtrlReg6Xl as u8, &mut rx_dat).map_err(|e| self.state.bus_error(e))?;
+        Ok(rx_dat[0])
+    }
+}
+
+impl<IF, E> Accelerometer for LSM9DS1<IF> where IF: RegisterInterface<Error = E>, E: Debug {
+    //Reads the acceleration in g, corrected with the calibration from calibration_info
+    fn read_acceleration(&mut self) -> Result<(f32, f32, f32), SensorError<E>> {
+        let raw = self.read_raw_acceleration()?;
+        Ok(self.scale_acceleration(raw))
+    }
+}
@@ -0,0 +1,83 @@
+use crate::sensor::calibration::{CalibrationError, PayloadReader, PayloadWriter, Persistent, KIND_LSM9DS1_CALIBRATION};
+use core::fmt::Debug;
+use super::interface::RegisterInterface;
+use super::lsm9ds1_s::{AccelerometerCalibration, CalibrationInfo, GyroCalibration, MagnetometerCalibration, LSM9DS1};
+
+impl Persistent for CalibrationInfo {
+    const KIND: u8 = KIND_LSM9DS1_CALIBRATION;
+    const VERSION: u8 = 2;
+    const PAYLOAD_LEN: usize = 100;
+
+    fn encode_payload(&self, payload: &mut PayloadWriter) {
+        let magnetometer = &self.magnetometer;
+        payload.write_i32(magnetometer.x_offset);
+        payload.write_i32(magnetometer.y_offset);
+        payload.write_i32(magnetometer.z_offset);
+        for value in magnetometer.soft_iron.iter().flatten() {
+            payload.write_f32(*value);
+        }
+
+        let gyroscope = &self.gyroscope;
+        for value in [gyroscope.x_bias, gyroscope.y_bias, gyroscope.z_bias, gyroscope.x_slope, gyroscope.y_slope, gyroscope.z_slope, gyroscope.reference_temperature] {
+            payload.write_f32(value);
+        }
+
+        let accelerometer = &self.accelerometer;
+        for value in [accelerometer.x_offset, accelerometer.y_offset, accelerometer.z_offset, accelerometer.x_scale, accelerometer.y_scale, accelerometer.z_scale] {
+            payload.write_f32(value);
+        }
+    }
+
+    fn decode_payload(payload: &mut PayloadReader) -> Self {
+        let mut magnetometer = MagnetometerCalibration {
+            x_offset: payload.read_i32(),
+            y_offset: payload.read_i32(),
+            z_offset: payload.read_i32(),
+            ..MagnetometerCalibration::default()
+        };
+        for value in magnetometer.soft_iron.iter_mut().flatten() {
+            *value = payload.read_f32();
+        }
+
+        let gyroscope = GyroCalibration {
+            x_bias: payload.read_f32(),
+            y_bias: payload.read_f32(),
+            z_bias: payload.read_f32(),
+            x_slope: payload.read_f32(),
+            y_slope: payload.read_f32(),
+            z_slope: payload.read_f32(),
+            reference_temperature: payload.read_f32()
+        };
+
+        let accelerometer = AccelerometerCalibration {
+            x_offset: payload.read_f32(),
+            y_offset: payload.read_f32(),
+            z_offset: payload.read_f32(),
+            x_scale: payload.read_f32(),
+            y_scale: payload.read_f32(),
+            z_scale: payload.read_f32()
+        };
+
+        CalibrationInfo {
+            magnetometer,
+            gyroscope,
+            accelerometer
+        }
+    }
+}
+
+impl<IF, E> LSM9DS1<IF> where IF: RegisterInterface<Error = E>, E: Debug {
+    //Encodes calibration_info into a record, returning its length in bytes
+    pub fn store_calibration(&self, buffer: &mut [u8]) -> Result<usize, CalibrationError> {
+        self.calibration_info.encode(buffer)
+    }
+
+    /*
+     * Restores calibration saved by store_calibration. The current calibration is kept if the record is invalid.
+     * Note that Sensor::calibrate re-estimates the gyro bias, replacing the one loaded here.
+     */
+    pub fn load_calibration(&mut self, buffer: &[u8]) -> Result<(), CalibrationError> {
+        self.calibration_info = CalibrationInfo::decode(buffer)?;
+        Ok(())
+    }
+}
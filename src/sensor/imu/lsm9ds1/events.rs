@@ -0,0 +1,78 @@
+use crate::sensor::SensorError;
+use core::fmt::Debug;
+use super::interface::{Device, RegisterInterface};
+use super::lsm9ds1_s::{AccelerometerEventConfig, AxisEvents, EventRM, GyroEventConfig, InertialEvents, LSM9DS1};
+
+/*
+ * The accelerometer and gyroscope interrupt generators compare each sample against per-axis thresholds in hardware,
+ * raising latched events for free-fall, wake-up, orientation or rotation without the thresholds being checked in
+ * software. Thresholds are converted using the full scale currently configured, so the generators should be
+ * reprogrammed after the full scale is changed.
+ */
+impl<IF, E> LSM9DS1<IF> where IF: RegisterInterface<Error = E>, E: Debug {
+    //Writes the thresholds and duration, LIR_XL1 in CTRL_REG4, and then enables the events in INT_GEN_CFG_XL
+    pub fn configure_accelerometer_events(&mut self, config: AccelerometerEventConfig) -> Result<(), SensorError<E>> {
+        //INT_GEN_THS_*_XL are compared against the upper 8 bits of the output, so 1 LSB is 128 output LSB
+        let step = self.accelerometer_config.full_scale.sensitivity() * 128.0;
+        let (x, y, z) = match (threshold_lsb(config.threshold.0, step, 0xFF), threshold_lsb(config.threshold.1, step, 0xFF), threshold_lsb(config.threshold.2, step, 0xFF)) {
+            (Some(x), Some(y), Some(z)) if config.duration <= 0x7F => (x as u8, y as u8, z as u8),
+            _ => return Err(SensorError::InvalidConfiguration)
+        };
+
+        let mut rx_dat: [u8; 1] = [0; 1];
+        self.interface.read_registers(Device::AccelGyro, EventRM::CtrlReg4 as u8, &mut rx_dat).map_err(|e| self.state.bus_error(e))?;
+        let ctrl_reg4 = match config.latched {
+            true => rx_dat[0] | 0b10,
+            false => rx_dat[0] & !0b10
+        };
+
+        self.interface.write_registers(Device::AccelGyro, EventRM::CtrlReg4 as u8, &[ctrl_reg4]).map_err(|e| self.state.bus_error(e))?;
+        self.interface.write_registers(Device::AccelGyro, EventRM::IntGenThsXXl as u8, &[x, y, z, config.int_gen_dur_xl()]).map_err(|e| self.state.bus_error(e))?;
+        self.interface.write_registers(Device::AccelGyro, EventRM::IntGenCfgXl as u8, &[config.int_gen_cfg_xl()]).map_err(|e| self.state.bus_error(e))
+    }
+
+    //Writes the thresholds and duration, and then enables the events in INT_GEN_CFG_G
+    pub fn configure_gyro_events(&mut self, config: GyroEventConfig) -> Result<(), SensorError<E>> {
+        let step = self.gyro_config.full_scale.sensitivity();
+        let (x, y, z) = match (threshold_lsb(config.threshold.0, step, 0x7FFF), threshold_lsb(config.threshold.1, step, 0x7FFF), threshold_lsb(config.threshold.2, step, 0x7FFF)) {
+            (Some(x), Some(y), Some(z)) if config.duration <= 0x7F => (x, y, z),
+            _ => return Err(SensorError::InvalidConfiguration)
+        };
+
+        //DCRM_G shares the top bit of INT_GEN_THS_XH_G
+        let [x_low, x_high] = (x | ((config.decrement as u16) << 15)).to_le_bytes();
+        let [y_low, y_high] = y.to_le_bytes();
+        let [z_low, z_high] = z.to_le_bytes();
+
+        self.interface.write_registers(Device::AccelGyro, EventRM::IntGenThsXHG as u8, &[x_high, x_low, y_high, y_low, z_high, z_low, config.int_gen_dur_g()]).map_err(|e| self.state.bus_error(e))?;
+        self.interface.write_registers(Device::AccelGyro, EventRM::IntGenCfgG as u8, &[config.int_gen_cfg_g()]).map_err(|e| self.state.bus_error(e))
+    }
+
+    pub fn read_accelerometer_events(&mut self) -> Result<InertialEvents, SensorError<E>> {
+        let mut rx_dat: [u8; 1] = [0; 1];
+        self.interface.read_registers(Device::AccelGyro, EventRM::IntGenSrcXl as u8, &mut rx_dat).map_err(|e| self.state.bus_error(e))?;
+        Ok(inertial_events(rx_dat[0]))
+    }
+
+    pub fn read_gyro_events(&mut self) -> Result<InertialEvents, SensorError<E>> {
+        let mut rx_dat: [u8; 1] = [0; 1];
+        self.interface.read_registers(Device::AccelGyro, EventRM::IntGenSrcG as u8, &mut rx_dat).map_err(|e| self.state.bus_error(e))?;
+        Ok(inertial_events(rx_dat[0]))
+    }
+}
+
+//Converts a threshold to register steps, rounding to the nearest step. None if it is negative or does not fit
+fn threshold_lsb(threshold: f32, step: f32, max: u16) -> Option<u16> {
+    let steps = threshold / step;
+    match (0.0..=max as f32).contains(&steps) {
+        true => Some(((steps + 0.5) as u16).min(max)),
+        false => None
+    }
+}
+
+fn inertial_events(source: u8) -> InertialEvents {
+    InertialEvents {
+        events: AxisEvents::from_bits(source),
+        active: source & 0x40 != 0
+    }
+}
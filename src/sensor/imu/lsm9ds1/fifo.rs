@@ -0,0 +1,74 @@
+use crate::sensor::{SensorError, SensorState};
+use core::fmt::Debug;
+use super::interface::{Device, RegisterInterface};
+use super::lsm9ds1_s::{FifoConfig, FifoMode, FifoRM, FifoSample, FifoStatus, LSM9DS1};
+
+/*
+ * The FIFO buffers up to 32 accelerometer and gyroscope samples, so that they can be sampled at a high rate
+ * and read in batches. The FIFO stores samples at the gyroscope ODR, or the accelerometer ODR when the gyroscope
+ * is powered down.
+ */
+impl<IF, E> LSM9DS1<IF> where IF: RegisterInterface<Error = E>, E: Debug {
+    //Enables the FIFO (FIFO_EN in CTRL_REG9) unless the mode is bypass, and writes the mode and threshold to FIFO_CTRL
+    pub fn configure_fifo(&mut self, config: FifoConfig) -> Result<(), SensorError<E>> {
+        if config.threshold > 31 {
+            return Err(SensorError::InvalidConfiguration);
+        }
+
+        let mut rx_dat: [u8; 1] = [0; 1];
+        self.interface.read_registers(Device::AccelGyro, FifoRM::CtrlReg9 as u8, &mut rx_dat).map_err(|e| self.state.bus_error(e))?;
+        let ctrl_reg9 = match config.mode {
+            FifoMode::Bypass => rx_dat[0] & !0b10,
+            _ => rx_dat[0] | 0b10
+        };
+
+        self.interface.write_registers(Device::AccelGyro, FifoRM::CtrlReg9 as u8, &[ctrl_reg9]).map_err(|e| self.state.bus_error(e))?;
+        self.interface.write_registers(Device::AccelGyro, FifoRM::FifoCtrl as u8, &[config.fifo_ctrl()]).map_err(|e| self.state.bus_error(e))?;
+        Ok(())
+    }
+
+    pub fn read_fifo_status(&mut self) -> Result<FifoStatus, SensorError<E>> {
+        let mut rx_dat: [u8; 1] = [0; 1];
+        self.interface.read_registers(Device::AccelGyro, FifoRM::FifoSrc as u8, &mut rx_dat).map_err(|e| self.state.bus_error(e))?;
+
+        Ok(FifoStatus {
+            samples: rx_dat[0] & 0x3F,
+            threshold_reached: rx_dat[0] & 0x80 != 0,
+            overrun: rx_dat[0] & 0x40 != 0
+        })
+    }
+
+    /*
+     * Reads the samples reported by FIFO_SRC into samples, oldest first, and returns the number read. Samples which do
+     * not fit are left in the FIFO. now_us is the current time in µs: the newest sample in the FIFO is stamped with it,
+     * and older samples are stamped one sample period earlier each.
+     */
+    pub fn read_fifo(&mut self, samples: &mut [FifoSample], now_us: u32) -> Result<usize, SensorError<E>> {
+        let stored = self.read_fifo_status()?.samples as u32;
+        let count = samples.len().min(stored as usize);
+
+        let period = match self.gyro_config.odr.sample_period_us().or(self.accelerometer_config.odr.sample_period_us()) {
+            Some(period) => period,
+            None => {
+                self.state = SensorState::ERROR(SensorError::InvalidConfiguration);
+                return Err(SensorError::InvalidConfiguration);
+            }
+        };
+
+        //The die temperature changes slowly, so one reading covers the whole batch
+        let temperature = self.gyro_temperature()?;
+        for (i, sample) in samples[..count].iter_mut().enumerate() {
+            //Reading both output register blocks moves the FIFO on to the next sample
+            let gyro = self.read_raw_gyro()?;
+            let acceleration = self.read_raw_acceleration()?;
+
+            *sample = FifoSample {
+                timestamp_us: now_us.wrapping_sub((stored - 1 - i as u32) * period),
+                acceleration: self.scale_acceleration(acceleration),
+                gyro: self.scale_gyro(gyro, temperature)
+            };
+        }
+
+        Ok(count)
+    }
+}
@@ -1,52 +1,205 @@
+use crate::sensor::{SensorError, SensorState};
 use crate::sensor::imu::Gyroscope;
-use cortex_m::asm::nop;
-use stm32f4xx_hal::i2c::Instance as I2cInstance;
-use super::lsm9ds1_s::{GyroOdr, GyroRM, LSM9DS1};
+use core::fmt::Debug;
+use super::interface::{Device, RegisterInterface};
+use super::lsm9ds1_s::{GyroCalibration, GyroConfig, GyroRM, GyroTemperatureCalibrationSession, LSM9DS1, GYRO_TEMPERATURE_MIN_SPAN, STATUS_GDA};
 
-impl<'a, T  > LSM9DS1<'a, T> where T: I2cInstance {
-    pub fn boot_gyroscope(&mut self) -> Result<(), ()> {
-        let odr = GyroOdr::CONTINUOUS;
-        let mut reg_value = (odr as u8) << 5;
-        reg_value = reg_value | 0b00011;
+impl GyroTemperatureCalibrationSession {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_point(&mut self, temperature: f32, bias: (f32, f32, f32)) {
+        let t = temperature as f64;
+        self.sums[0] += t;
+        self.sums[1] += t * t;
+        for (sums, b) in self.bias_sums.iter_mut().zip([bias.0, bias.1, bias.2]) {
+            sums[0] += b as f64;
+            sums[1] += t * b as f64;
+        }
+
+        self.min_temperature = self.min_temperature.min(temperature);
+        self.max_temperature = self.max_temperature.max(temperature);
+        self.points += 1;
+    }
+
+    //Fits the bias of each axis as a line in temperature, referenced to the mean temperature of the points
+    pub fn finish(&self) -> Result<GyroCalibration, SensorError> {
+        if self.points < 2 || self.max_temperature - self.min_temperature < GYRO_TEMPERATURE_MIN_SPAN {
+            return Err(SensorError::FailedToCalibrate);
+        }
+
+        let count = self.points as f64;
+        let mean = self.sums[0] / count;
+        let spread = self.sums[1] - count * mean * mean;
+        let [x, y, z] = self.bias_sums.map(|sums| {
+            let bias = sums[0] / count;
+            (bias, (sums[1] - count * mean * bias) / spread)
+        });
+
+        Ok(GyroCalibration {
+            x_bias: x.0 as f32,
+            y_bias: y.0 as f32,
+            z_bias: z.0 as f32,
+            x_slope: x.1 as f32,
+            y_slope: y.1 as f32,
+            z_slope: z.1 as f32,
+            reference_temperature: mean as f32
+        })
+    }
+}
 
-        let initial_value = self.read_ctrl_reg1_g();
-        let res = self.i2c.write(self.addr, &[GyroRM::CtrlReg1G as u8, reg_value]);
-        let final_value = self.read_ctrl_reg1_g();
+impl<IF, E> LSM9DS1<IF> where IF: RegisterInterface<Error = E>, E: Debug {
+    pub fn boot_gyroscope(&mut self) -> Result<(), SensorError<E>> {
+        self.configure_gyroscope(self.gyro_config)
+    }
 
-        if initial_value != final_value && final_value == reg_value {
+    //Writes the configuration to CTRL_REG1_G - CTRL_REG3_G. Readings are scaled using the new full scale from then on
+    pub fn configure_gyroscope(&mut self, config: GyroConfig) -> Result<(), SensorError<E>> {
+        if let Some(cutoff) = config.high_pass_cutoff {
+            if cutoff > 9 {
+                return Err(SensorError::InvalidConfiguration);
+            }
+        }
+
+        let reg1_value = config.ctrl_reg1_g();
+
+        self.interface.write_registers(Device::AccelGyro, GyroRM::CtrlReg1G as u8, &[reg1_value]).map_err(|e| self.state.bus_error(e))?;
+        self.interface.write_registers(Device::AccelGyro, GyroRM::CtrlReg2G as u8, &[config.ctrl_reg2_g()]).map_err(|e| self.state.bus_error(e))?;
+        self.interface.write_registers(Device::AccelGyro, GyroRM::CtrlReg3G as u8, &[config.ctrl_reg3_g()]).map_err(|e| self.state.bus_error(e))?;
+        let final_value = self.read_ctrl_reg1_g()?;
+
+        if final_value == reg1_value {
+            self.gyro_config = config;
             Ok(())
         } else {
-            Err(())
+            self.state = SensorState::ERROR(SensorError::InvalidConfiguration);
+            Err(SensorError::InvalidConfiguration)
         }
     }
     
-    pub fn calibrate_gyroscope(&mut self) {
-        //todo
-        //todo!();
+    /*
+     * Estimates the zero-rate level by averaging samples while the board is at rest, and stores it in calibration_info.
+     * The run is rejected if the standard deviation of any axis exceeds max_deviation (dps), as the board was moving.
+     * Temperature slopes from an earlier temperature calibration are kept, and re-referenced to the current temperature.
+     */
+    pub fn calibrate_gyroscope(&mut self, samples: u32, max_deviation: f32) -> Result<GyroCalibration, SensorError<E>> {
+        let bias = self.estimate_gyro_bias(samples, max_deviation)?;
+        let temperature = self.read_temperature()?;
+
+        let calibration = GyroCalibration {
+            x_bias: bias.0,
+            y_bias: bias.1,
+            z_bias: bias.2,
+            reference_temperature: temperature,
+            ..self.calibration_info.gyroscope
+        };
+        self.calibration_info.gyroscope = calibration;
+        Ok(calibration)
     }
 
-    fn read_ctrl_reg1_g (&mut self) -> u8 {
-        let mut rx_dat: [u8; 1] = [0; 1];
-        let res = self.i2c.write_read(self.addr, &[GyroRM::CtrlReg1G as u8], &mut rx_dat);
-        rx_dat[0]
+    //Estimates the bias at the current die temperature and adds it to the session. Repeat as the board warms up at rest
+    pub fn sample_gyro_temperature_calibration(&mut self, session: &mut GyroTemperatureCalibrationSession, samples: u32, max_deviation: f32) -> Result<(), SensorError<E>> {
+        let before = self.read_temperature()?;
+        let bias = self.estimate_gyro_bias(samples, max_deviation)?;
+        let after = self.read_temperature()?;
+
+        session.add_point((before + after) / 2.0, bias);
+        Ok(())
+    }
+
+    //Stores the temperature model fitted by the session in calibration_info
+    pub fn calibrate_gyro_temperature(&mut self, session: &GyroTemperatureCalibrationSession) -> Result<GyroCalibration, SensorError<E>> {
+        match session.finish() {
+            Ok(calibration) => {
+                self.calibration_info.gyroscope = calibration;
+                Ok(calibration)
+            }
+            Err(_) => {
+                self.state = SensorState::ERROR(SensorError::FailedToCalibrate);
+                Err(SensorError::FailedToCalibrate)
+            }
+        }
     }
-}
 
+    fn estimate_gyro_bias(&mut self, samples: u32, max_deviation: f32) -> Result<(f32, f32, f32), SensorError<E>> {
+        if samples == 0 {
+            return Err(SensorError::InvalidConfiguration);
+        }
 
-impl<'a, T> Gyroscope for LSM9DS1<'a, T> where T: I2cInstance {
-    fn read_gyro(&mut self) -> (f32, f32, f32) {
-        let range = 2000; //2000dps max reading
+        //Raw values are summed exactly, so the variance does not depend on the order of the samples
+        let mut sums = [0i64; 3];
+        let mut squares = [0i64; 3];
+        for _ in 0..samples {
+            self.wait_for_data(STATUS_GDA)?;
+            let (x, y, z) = self.read_raw_gyro()?;
+            for (axis, value) in [x, y, z].iter().enumerate() {
+                sums[axis] += *value as i64;
+                squares[axis] += *value as i64 * *value as i64;
+            }
+        }
 
-        let mut rx_buffer: [u8; 6] = [0; 6];
-        let res = self.i2c.write_read(self.addr, &[GyroRM::OutXGL as u8], &mut rx_buffer);
-        let x_raw = self.twos_complement(rx_buffer[1], rx_buffer[0]);
-        let y_raw = self.twos_complement(rx_buffer[3], rx_buffer[2]);
-        let z_raw = self.twos_complement(rx_buffer[5], rx_buffer[4]);
-        
-        let x = ((x_raw as i32 * range as i32) as f64 / i16::max_value() as f64) as f32;
-        let y = ((y_raw as i32 * range as i32) as f64 / i16::max_value() as f64) as f32;
-        let z = ((z_raw as i32 * range as i32) as f64 / i16::max_value() as f64) as f32;
+        let sensitivity = self.gyro_config.full_scale.sensitivity() as f64;
+        let count = samples as f64;
+        let max_variance = (max_deviation as f64 / sensitivity) * (max_deviation as f64 / sensitivity);
+        let mut bias = [0f32; 3];
+        for axis in 0..3 {
+            let mean = sums[axis] as f64 / count;
+            let variance = squares[axis] as f64 / count - mean * mean;
+            if variance > max_variance {
+                self.state = SensorState::ERROR(SensorError::FailedToCalibrate);
+                return Err(SensorError::FailedToCalibrate);
+            }
+            bias[axis] = (mean * sensitivity) as f32;
+        }
+
+        Ok((bias[0], bias[1], bias[2]))
+    }
+
+    //Die temperature for bias compensation. Only read from the sensor if the calibration has a temperature model
+    pub(crate) fn gyro_temperature(&mut self) -> Result<f32, SensorError<E>> {
+        let calibration = self.calibration_info.gyroscope;
+        match calibration.is_temperature_compensated() {
+            true => self.read_temperature(),
+            false => Ok(calibration.reference_temperature)
+        }
+    }
+
+    //Converts a raw reading to dps, and removes the bias from calibration_info at the given die temperature
+    pub(crate) fn scale_gyro(&self, raw: (i16, i16, i16), temperature: f32) -> (f32, f32, f32) {
+        let sensitivity = self.gyro_config.full_scale.sensitivity();
+        let bias = self.calibration_info.gyroscope.bias_at(temperature);
+
+        let x = raw.0 as f32 * sensitivity - bias.0;
+        let y = raw.1 as f32 * sensitivity - bias.1;
+        let z = raw.2 as f32 * sensitivity - bias.2;
 
         (x, y, z)
     }
-}
\ No newline at end of file
+
+    pub fn read_raw_gyro(&mut self) -> Result<(i16, i16, i16), SensorError<E>> {
+        let mut rx_buffer: [u8; 6] = [0; 6];
+        self.interface.read_registers(Device::AccelGyro, GyroRM::OutXGL as u8, &mut rx_buffer).map_err(|e| self.state.bus_error(e))?;
+        let x = self.twos_complement(rx_buffer[1], rx_buffer[0]);
+        let y = self.twos_complement(rx_buffer[3], rx_buffer[2]);
+        let z = self.twos_complement(rx_buffer[5], rx_buffer[4]);
+
+        Ok((x, y, z))
+    }
+
+    fn read_ctrl_reg1_g (&mut self) -> Result<u8, SensorError<E>> {
+        let mut rx_dat: [u8; 1] = [0; 1];
+        self.interface.read_registers(Device::AccelGyro, GyroRM::CtrlReg1G as u8, &mut rx_dat).map_err(|e| self.state.bus_error(e))?;
+        Ok(rx_dat[0])
+    }
+}
+
+
+impl<IF, E> Gyroscope for LSM9DS1<IF> where IF: RegisterInterface<Error = E>, E: Debug {
+    //Reads the angular rate in dps, with the bias from calibration_info removed
+    fn read_gyro(&mut self) -> Result<(f32, f32, f32), SensorError<E>> {
+        let temperature = self.gyro_temperature()?;
+        let raw = self.read_raw_gyro()?;
+        Ok(self.scale_gyro(raw, temperature))
+    }
+}
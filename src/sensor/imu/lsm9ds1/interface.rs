@@ -0,0 +1,166 @@
+/*
+ * Register access for the LSM9DS1 over I2C or SPI. The accelerometer/gyroscope and the magnetometer are separate
+ * devices on the bus: two addresses over I2C, or two chip-selects over SPI. The driver only reads and writes blocks
+ * of consecutive registers, and the interface takes care of how the bus addresses them.
+ *
+ * The accelerometer/gyroscope auto-increments the register address during bursts on both buses (IF_ADD_INC in
+ * CTRL_REG8, set after reset). The magnetometer only does so when asked to in the address byte, which the interfaces
+ * do for any transfer of more than one register.
+ */
+use core::fmt::Debug;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use embedded_hal::blocking::spi;
+use embedded_hal::digital::v2::OutputPin;
+
+pub const I2C_ADDR: u8 = 0x6B; //Accelerometer and gyroscope, with SDO_A/G pulled high
+pub const I2C_M_ADDR: u8 = 0x1E; //Magnetometer, with SDO_M pulled high
+
+const I2C_MAG_AUTO_INCREMENT: u8 = 0x80;
+const SPI_READ: u8 = 0x80;
+const SPI_MAG_AUTO_INCREMENT: u8 = 0x40;
+const MAX_WRITE_LEN: usize = 8; //Longer I2C writes are split into several transactions
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Device {
+    AccelGyro,
+    Magnetometer
+}
+
+pub trait RegisterInterface {
+    type Error: Debug;
+
+    //Bits which must stay set in CTRL_REG3_M for the magnetometer to be readable over this interface
+    const CTRL_REG3_M: u8 = 0;
+
+    //Writes data to consecutive registers, starting at register
+    fn write_registers(&mut self, device: Device, register: u8, data: &[u8]) -> Result<(), Self::Error>;
+
+    //Fills buffer from consecutive registers, starting at register
+    fn read_registers(&mut self, device: Device, register: u8, buffer: &mut [u8]) -> Result<(), Self::Error>;
+}
+
+pub struct I2cInterface<'a, I2C> {
+    pub i2c: &'a mut I2C,
+    pub addr: u8, //Accelerometer and Gyroscope address
+    pub m_addr: u8 //Magnetometer address
+}
+
+impl<'a, I2C> I2cInterface<'a, I2C> {
+    pub fn new(i2c: &'a mut I2C) -> Self {
+        I2cInterface {
+            i2c,
+            addr: I2C_ADDR,
+            m_addr: I2C_M_ADDR
+        }
+    }
+
+    //Bus address and sub-address for a transfer of len registers
+    fn sub_address(&self, device: Device, register: u8, len: usize) -> (u8, u8) {
+        match device {
+            Device::AccelGyro => (self.addr, register),
+            Device::Magnetometer if len > 1 => (self.m_addr, register | I2C_MAG_AUTO_INCREMENT),
+            Device::Magnetometer => (self.m_addr, register)
+        }
+    }
+}
+
+impl<'a, I2C, E> RegisterInterface for I2cInterface<'a, I2C> where I2C: Write<Error = E> + WriteRead<Error = E>, E: Debug {
+    type Error = E;
+
+    fn write_registers(&mut self, device: Device, register: u8, data: &[u8]) -> Result<(), E> {
+        //The sub-address and data have to go out in a single transaction, so long writes are sent in chunks
+        for (i, chunk) in data.chunks(MAX_WRITE_LEN).enumerate() {
+            let (addr, sub_address) = self.sub_address(device, register.wrapping_add((i * MAX_WRITE_LEN) as u8), chunk.len());
+
+            let mut tx_buffer: [u8; MAX_WRITE_LEN + 1] = [0; MAX_WRITE_LEN + 1];
+            tx_buffer[0] = sub_address;
+            tx_buffer[1..=chunk.len()].copy_from_slice(chunk);
+            self.i2c.write(addr, &tx_buffer[..=chunk.len()])?;
+        }
+
+        Ok(())
+    }
+
+    fn read_registers(&mut self, device: Device, register: u8, buffer: &mut [u8]) -> Result<(), E> {
+        let (addr, sub_address) = self.sub_address(device, register, buffer.len());
+        self.i2c.write_read(addr, &[sub_address], buffer)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SpiInterfaceError<SE, AGE, ME> {
+    Spi(SE),
+    AccelGyroChipSelect(AGE),
+    MagnetometerChipSelect(ME)
+}
+
+/*
+ * 4-wire SPI, mode 3 (CPOL = 1, CPHA = 1), up to 10 MHz. Each device has its own active-low chip-select, which is
+ * released after every transfer. The magnetometer only allows reads over SPI once SIM is set in CTRL_REG3_M, so
+ * the driver keeps that bit set whenever it writes the register.
+ */
+pub struct SpiInterface<'a, SPI, CSAG, CSM> {
+    pub spi: &'a mut SPI, //Borrowed like the I2C bus, so the same bus can be used for other devices
+    pub cs_ag: CSAG, //Accelerometer and gyroscope chip-select (CS_A/G)
+    pub cs_m: CSM //Magnetometer chip-select (CS_M)
+}
+
+impl<'a, SPI, CSAG, CSM> SpiInterface<'a, SPI, CSAG, CSM> {
+    pub fn new(spi: &'a mut SPI, cs_ag: CSAG, cs_m: CSM) -> Self {
+        SpiInterface {
+            spi,
+            cs_ag,
+            cs_m
+        }
+    }
+}
+
+impl<'a, SPI, CSAG, CSM, SE, AGE, ME> SpiInterface<'a, SPI, CSAG, CSM> where SPI: spi::Write<u8, Error = SE> + spi::Transfer<u8, Error = SE>, CSAG: OutputPin<Error = AGE>, CSM: OutputPin<Error = ME> {
+    //Runs a transfer with the device selected. The chip-select is released even if the transfer fails
+    fn transaction<F>(&mut self, device: Device, transfer: F) -> Result<(), SpiInterfaceError<SE, AGE, ME>> where F: FnOnce(&mut SPI) -> Result<(), SE> {
+        self.select(device, false)?;
+        let result = transfer(self.spi).map_err(SpiInterfaceError::Spi);
+        self.select(device, true)?;
+        result
+    }
+
+    fn select(&mut self, device: Device, released: bool) -> Result<(), SpiInterfaceError<SE, AGE, ME>> {
+        match (device, released) {
+            (Device::AccelGyro, false) => self.cs_ag.set_low().map_err(SpiInterfaceError::AccelGyroChipSelect),
+            (Device::AccelGyro, true) => self.cs_ag.set_high().map_err(SpiInterfaceError::AccelGyroChipSelect),
+            (Device::Magnetometer, false) => self.cs_m.set_low().map_err(SpiInterfaceError::MagnetometerChipSelect),
+            (Device::Magnetometer, true) => self.cs_m.set_high().map_err(SpiInterfaceError::MagnetometerChipSelect)
+        }
+    }
+
+    fn address(device: Device, register: u8, len: usize) -> u8 {
+        match device {
+            Device::Magnetometer if len > 1 => register | SPI_MAG_AUTO_INCREMENT,
+            _ => register
+        }
+    }
+}
+
+impl<'a, SPI, CSAG, CSM, SE, AGE, ME> RegisterInterface for SpiInterface<'a, SPI, CSAG, CSM> where SPI: spi::Write<u8, Error = SE> + spi::Transfer<u8, Error = SE>, CSAG: OutputPin<Error = AGE>, CSM: OutputPin<Error = ME>, SE: Debug, AGE: Debug, ME: Debug {
+    type Error = SpiInterfaceError<SE, AGE, ME>;
+
+    const CTRL_REG3_M: u8 = 0b100; //SIM, enables SPI reads
+
+    fn write_registers(&mut self, device: Device, register: u8, data: &[u8]) -> Result<(), Self::Error> {
+        let address = Self::address(device, register, data.len());
+        self.transaction(device, |spi| {
+            spi.write(&[address])?;
+            spi.write(data)
+        })
+    }
+
+    fn read_registers(&mut self, device: Device, register: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        let address = Self::address(device, register, buffer.len()) | SPI_READ;
+        self.transaction(device, |spi| {
+            spi.write(&[address])?;
+            buffer.fill(0);
+            spi.transfer(buffer)?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,80 @@
+use crate::sensor::SensorError;
+use core::fmt::Debug;
+use super::interface::{Device, RegisterInterface};
+use super::lsm9ds1_s::{GyroRM, ImuStatus, Int1Config, Int2Config, InterruptRM, MagInterruptConfig, MagInterruptSource, MagnetometerRM, MagnetometerStatus, LSM9DS1, STATUS_GDA, STATUS_TDA, STATUS_XLDA};
+
+/*
+ * Interrupt pins and new data flags, so that samples can be read once each as they arrive instead of polling
+ * the output registers on a timer.
+ */
+impl<IF, E> LSM9DS1<IF> where IF: RegisterInterface<Error = E>, E: Debug {
+    pub fn configure_int1(&mut self, config: Int1Config) -> Result<(), SensorError<E>> {
+        self.interface.write_registers(Device::AccelGyro, InterruptRM::Int1Ctrl as u8, &[config.int1_ctrl()]).map_err(|e| self.state.bus_error(e))
+    }
+
+    pub fn configure_int2(&mut self, config: Int2Config) -> Result<(), SensorError<E>> {
+        self.interface.write_registers(Device::AccelGyro, InterruptRM::Int2Ctrl as u8, &[config.int2_ctrl()]).map_err(|e| self.state.bus_error(e))
+    }
+
+    //Writes the threshold to INT_THS_L_M/INT_THS_H_M, then enables the interrupt in INT_CFG_M
+    pub fn configure_magnetometer_interrupt(&mut self, config: MagInterruptConfig) -> Result<(), SensorError<E>> {
+        if config.threshold > 0x7FFF {
+            return Err(SensorError::InvalidConfiguration);
+        }
+
+        let [low, high] = config.threshold.to_le_bytes();
+        self.interface.write_registers(Device::Magnetometer, MagnetometerRM::IntThsLM as u8, &[low]).map_err(|e| self.state.bus_error(e))?;
+        self.interface.write_registers(Device::Magnetometer, MagnetometerRM::IntThsHM as u8, &[high]).map_err(|e| self.state.bus_error(e))?;
+        self.interface.write_registers(Device::Magnetometer, MagnetometerRM::IntCfgM as u8, &[config.int_cfg_m()]).map_err(|e| self.state.bus_error(e))
+    }
+
+    pub fn read_status(&mut self) -> Result<ImuStatus, SensorError<E>> {
+        let mut rx_dat: [u8; 1] = [0; 1];
+        self.interface.read_registers(Device::AccelGyro, GyroRM::StatusReg as u8, &mut rx_dat).map_err(|e| self.state.bus_error(e))?;
+        let status = rx_dat[0];
+
+        Ok(ImuStatus {
+            accelerometer_data: status & STATUS_XLDA != 0,
+            gyro_data: status & STATUS_GDA != 0,
+            temperature_data: status & STATUS_TDA != 0,
+            accelerometer_event: status & 0x40 != 0,
+            gyro_event: status & 0x20 != 0,
+            inactivity: status & 0x10 != 0
+        })
+    }
+
+    pub fn read_magnetometer_status(&mut self) -> Result<MagnetometerStatus, SensorError<E>> {
+        let mut rx_dat: [u8; 1] = [0; 1];
+        self.interface.read_registers(Device::Magnetometer, MagnetometerRM::StatusRegM as u8, &mut rx_dat).map_err(|e| self.state.bus_error(e))?;
+
+        Ok(MagnetometerStatus {
+            data: rx_dat[0] & 0x08 != 0,
+            overrun: rx_dat[0] & 0x80 != 0
+        })
+    }
+
+    pub fn read_magnetometer_interrupt_source(&mut self) -> Result<MagInterruptSource, SensorError<E>> {
+        let mut rx_dat: [u8; 1] = [0; 1];
+        self.interface.read_registers(Device::Magnetometer, MagnetometerRM::IntSrcM as u8, &mut rx_dat).map_err(|e| self.state.bus_error(e))?;
+        let source = rx_dat[0];
+
+        Ok(MagInterruptSource {
+            positive: (source & 0x80 != 0, source & 0x40 != 0, source & 0x20 != 0),
+            negative: (source & 0x10 != 0, source & 0x08 != 0, source & 0x04 != 0),
+            active: source & 0x01 != 0
+        })
+    }
+
+    //True if a new accelerometer sample is available since the output registers were last read
+    pub fn accelerometer_data_ready(&mut self) -> Result<bool, SensorError<E>> {
+        Ok(self.read_status()?.accelerometer_data)
+    }
+
+    pub fn gyro_data_ready(&mut self) -> Result<bool, SensorError<E>> {
+        Ok(self.read_status()?.gyro_data)
+    }
+
+    pub fn magnetometer_data_ready(&mut self) -> Result<bool, SensorError<E>> {
+        Ok(self.read_magnetometer_status()?.data)
+    }
+}
@@ -1,46 +1,73 @@
 use byteorder::{ByteOrder, LittleEndian};
-use cortex_m::asm::nop;
-use stm32f4xx_hal::{i2c::{I2c, Instance as I2cInstance}, pac::TIM1, timer::DelayMs};
-use embedded_hal::prelude::_embedded_hal_blocking_delay_DelayMs; //Bring the DelayMs trait into scope
+use core::fmt::Debug;
+use super::interface::{Device, I2cInterface, RegisterInterface, SpiInterface};
 use crate::sensor::{SensorError, SensorState};
-use super::lsm9ds1_s::{CalibrationInfo, ImuData, MagnetometerRM, XlOdr, LSM9DS1};
+use super::lsm9ds1_s::{AccelerometerConfig, CalibrationInfo, GyroConfig, GyroRM, ImuData, MagMode, MagnetometerConfig, MagnetometerRM, LSM9DS1, DATA_READY_POLLS};
 
-impl<'a, T  > LSM9DS1<'a, T> where T: I2cInstance {
-    pub fn new(i2c: &'a mut I2c<T>) -> Self {
+impl<'a, I2C> LSM9DS1<I2cInterface<'a, I2C>> {
+    //Accelerometer and gyroscope at 0x6B, magnetometer at 0x1E
+    pub fn new(i2c: &'a mut I2C) -> Self {
+        Self::with_interface(I2cInterface::new(i2c))
+    }
+}
+
+impl<'a, SPI, CSAG, CSM> LSM9DS1<SpiInterface<'a, SPI, CSAG, CSM>> {
+    pub fn new_spi(spi: &'a mut SPI, cs_ag: CSAG, cs_m: CSM) -> Self {
+        Self::with_interface(SpiInterface::new(spi, cs_ag, cs_m))
+    }
+}
+
+impl<IF> LSM9DS1<IF> {
+    pub fn with_interface(interface: IF) -> Self {
         LSM9DS1 {
-            m_addr: 0x1E,
-            addr: 0x6B,
-            i2c,
+            interface,
             state: SensorState::INITIAL,
             data: ImuData::new(),
-            calibration_info: CalibrationInfo::new()
+            calibration_info: CalibrationInfo::new(),
+            accelerometer_config: AccelerometerConfig::default(),
+            gyro_config: GyroConfig::default(),
+            magnetometer_config: MagnetometerConfig::default()
         }
     }
+}
 
+impl<IF, E> LSM9DS1<IF> where IF: RegisterInterface<Error = E>, E: Debug {
     //Sanity check to ensure the sensor is powered on and accessible
-    pub fn sanity_check(&mut self) -> bool {
-        let mut rx_buffer: [u8; 2] = [0; 2];
-
-        //Read the id from the sensor to confirm it is powered on and accessible
-        let res = self.i2c.write_read(self.m_addr, &[MagnetometerRM::WhoAmI as u8], &mut rx_buffer);
-
-        //Check if an i2c error occured. If so, it would be due to the sensor not being found (incorrect i2c address or not powered on)
-        match res {
-            Ok(_) => {},
-            Err(_) => {
-                self.state = SensorState::ERROR(SensorError::NotFound);
-                return false;
-            }
+    pub fn sanity_check(&mut self) -> Result<(), SensorError<E>> {
+        let mut rx_buffer: [u8; 1] = [0; 1];
+
+        //Over SPI the magnetometer has to be told to allow reads first. It is left powered down until it is configured
+        if IF::CTRL_REG3_M != 0 {
+            let power_down = MagnetometerConfig { mode: MagMode::PowerDown, ..self.magnetometer_config };
+            self.interface.write_registers(Device::Magnetometer, MagnetometerRM::CtrlReg3M as u8, &[power_down.ctrl_reg3_m() | IF::CTRL_REG3_M]).map_err(|e| self.state.bus_error(e))?;
         }
 
-        return if rx_buffer[0] == 0x3D {
+        //Read the id from the sensor to confirm it is powered on and accessible. A bus error usually means an incorrect address or an unpowered sensor
+        self.interface.read_registers(Device::Magnetometer, MagnetometerRM::WhoAmI as u8, &mut rx_buffer).map_err(|e| self.state.bus_error(e))?;
+
+        if rx_buffer[0] == 0x3D {
             // Sensor detected
-            true
+            Ok(())
         } else {
             // Sensor not detected
-            self.state = SensorState::ERROR(SensorError::NotFound);
-            false
+            let error = SensorError::WrongId { expected: 0x3D, found: rx_buffer[0] };
+            self.state = SensorState::ERROR(error.kind());
+            Err(error)
+        }
+    }
+
+    //Polls STATUS_REG until any of the bits in mask are set, i.e. new data is available
+    pub(crate) fn wait_for_data(&mut self, mask: u8) -> Result<(), SensorError<E>> {
+        for _ in 0..DATA_READY_POLLS {
+            let mut rx_dat: [u8; 1] = [0; 1];
+            self.interface.read_registers(Device::AccelGyro, GyroRM::StatusReg as u8, &mut rx_dat).map_err(|e| self.state.bus_error(e))?;
+            if rx_dat[0] & mask != 0 {
+                return Ok(());
+            }
         }
+
+        self.state = SensorState::ERROR(SensorError::Timeout);
+        Err(SensorError::Timeout)
     }
 
     pub fn twos_complement(&self, high: u8, low: u8) -> i16 {
@@ -1,19 +1,14 @@
-use embedded_hal::blocking::i2c::WriteRead;
-use stm32f4xx_hal::{hal, pac::TIM1};
-use stm32f4xx_hal::i2c::{I2c, Instance as I2cInstance};
-use stm32f4xx_hal::timer::{DelayMs, Timer, TimerExt, Instance as TimerInstance};
-use stm32f4xx_hal::rcc::Clocks;
-use embedded_hal::prelude::*;
-
 use crate::sensor::{SensorState, SensorError};
 
+//Acceleration in g from the latest read_all
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
 pub struct ImuAccelerationData {
-    pub x: i32,
-    pub y: i32,
-    pub z: i32
+    pub x: f32,
+    pub y: f32,
+    pub z: f32
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum XlOdr {
     PowerDown = 0,
     Hz10 = 1,
@@ -21,66 +16,830 @@ pub enum XlOdr {
     Hz119 = 3,
     Hz238 = 4,
     Hz476 = 5,
-    Hz952 = 6,
-    CONTINUOUS = 7
+    Hz952 = 6
+}
+
+impl XlOdr {
+    //Time between samples in µs, or None if the accelerometer is not sampling at a defined rate
+    pub fn sample_period_us(&self) -> Option<u32> {
+        match self {
+            XlOdr::Hz10 => Some(100_000),
+            XlOdr::Hz50 => Some(20_000),
+            XlOdr::Hz119 => Some(8_403),
+            XlOdr::Hz238 => Some(4_202),
+            XlOdr::Hz476 => Some(2_101),
+            XlOdr::Hz952 => Some(1_050),
+            _ => None
+        }
+    }
+}
+
+//Accelerometer full scale. The discriminants are the FS_XL bits of CTRL_REG6_XL
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum XlFullScale {
+    G2 = 0,
+    G16 = 1,
+    G4 = 2,
+    G8 = 3
+}
+
+impl XlFullScale {
+    //Linear acceleration sensitivity in g/LSB. Refer to table 3 in the datasheet
+    pub fn sensitivity(&self) -> f32 {
+        match self {
+            XlFullScale::G2 => 0.000061,
+            XlFullScale::G4 => 0.000122,
+            XlFullScale::G8 => 0.000244,
+            XlFullScale::G16 => 0.000732
+        }
+    }
+}
+
+//Anti-aliasing filter bandwidth. Auto lets the sensor pick the bandwidth based on the ODR
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum XlAntiAliasing {
+    Auto,
+    Hz408,
+    Hz211,
+    Hz105,
+    Hz50
+}
+
+//Cutoff of the digital low-pass filter, applied in high resolution mode. Off disables high resolution mode
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum XlBandwidth {
+    Off,
+    OdrDiv50,
+    OdrDiv100,
+    OdrDiv9,
+    OdrDiv400
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AccelerometerConfig {
+    pub odr: XlOdr,
+    pub full_scale: XlFullScale,
+    pub anti_aliasing: XlAntiAliasing,
+    pub bandwidth: XlBandwidth
+}
+
+impl Default for AccelerometerConfig {
+    fn default() -> Self {
+        AccelerometerConfig {
+            odr: XlOdr::Hz952, //Same rate as the gyroscope
+            full_scale: XlFullScale::G2,
+            anti_aliasing: XlAntiAliasing::Auto,
+            bandwidth: XlBandwidth::Off
+        }
+    }
+}
+
+impl AccelerometerConfig {
+    pub fn ctrl_reg6_xl(&self) -> u8 {
+        let (bw_scal_odr, bw_xl) = match self.anti_aliasing {
+            XlAntiAliasing::Auto => (0, 0b00),
+            XlAntiAliasing::Hz408 => (1, 0b00),
+            XlAntiAliasing::Hz211 => (1, 0b01),
+            XlAntiAliasing::Hz105 => (1, 0b10),
+            XlAntiAliasing::Hz50 => (1, 0b11)
+        };
+
+        ((self.odr as u8) << 5) | ((self.full_scale as u8) << 3) | (bw_scal_odr << 2) | bw_xl
+    }
+
+    pub fn ctrl_reg7_xl(&self) -> u8 {
+        let (hr, dcf) = match self.bandwidth {
+            XlBandwidth::Off => (0, 0b00),
+            XlBandwidth::OdrDiv50 => (1, 0b00),
+            XlBandwidth::OdrDiv100 => (1, 0b01),
+            XlBandwidth::OdrDiv9 => (1, 0b10),
+            XlBandwidth::OdrDiv400 => (1, 0b11)
+        };
+
+        (hr << 7) | (dcf << 5)
+    }
 }
 
-#[derive(Debug, Copy, Clone)]
+//Refer to table 46 in the datasheet
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum GyroOdr {
     PowerDown = 0,
     Hz15 = 1,
     Hz60 = 2,
     Hz119 = 3,
     Hz238 = 4,
-    Hz952 = 5,
-    CONTINUOUS = 6
+    Hz476 = 5,
+    Hz952 = 6
+}
+
+impl GyroOdr {
+    //Time between samples in µs, or None if the gyroscope is powered down. Refer to table 46 in the datasheet for the exact rates
+    pub fn sample_period_us(&self) -> Option<u32> {
+        match self {
+            GyroOdr::PowerDown => None,
+            GyroOdr::Hz15 => Some(67_114),
+            GyroOdr::Hz60 => Some(16_807),
+            GyroOdr::Hz119 => Some(8_403),
+            GyroOdr::Hz238 => Some(4_202),
+            GyroOdr::Hz476 => Some(2_101),
+            GyroOdr::Hz952 => Some(1_050)
+        }
+    }
+}
+
+//Gyroscope full scale. The discriminants are the FS_G bits of CTRL_REG1_G
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GyroFullScale {
+    Dps245 = 0,
+    Dps500 = 1,
+    Dps2000 = 3
+}
+
+impl GyroFullScale {
+    //Angular rate sensitivity in dps/LSB. Refer to table 3 in the datasheet
+    pub fn sensitivity(&self) -> f32 {
+        match self {
+            GyroFullScale::Dps245 => 0.00875,
+            GyroFullScale::Dps500 => 0.0175,
+            GyroFullScale::Dps2000 => 0.07
+        }
+    }
+}
+
+//Cutoff of the second low-pass filter (BW_G). The actual frequency depends on the ODR, refer to table 47 in the datasheet
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GyroBandwidth {
+    Narrowest = 0,
+    Narrow = 1,
+    Wide = 2,
+    Widest = 3
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GyroConfig {
+    pub odr: GyroOdr,
+    pub full_scale: GyroFullScale,
+    pub bandwidth: GyroBandwidth,
+    pub high_pass_cutoff: Option<u8> //HPCF_G value (0-9), or None to disable the high-pass filter. Refer to table 52 in the datasheet
+}
+
+impl Default for GyroConfig {
+    fn default() -> Self {
+        GyroConfig {
+            odr: GyroOdr::Hz952,
+            full_scale: GyroFullScale::Dps2000,
+            bandwidth: GyroBandwidth::Widest,
+            high_pass_cutoff: None
+        }
+    }
+}
+
+impl GyroConfig {
+    pub fn ctrl_reg1_g(&self) -> u8 {
+        ((self.odr as u8) << 5) | ((self.full_scale as u8) << 3) | self.bandwidth as u8
+    }
+
+    //Output data always goes through the second low-pass filter (OUT_SEL = 10), so that the bandwidth setting applies
+    pub fn ctrl_reg2_g(&self) -> u8 {
+        0b10
+    }
+
+    pub fn ctrl_reg3_g(&self) -> u8 {
+        match self.high_pass_cutoff {
+            Some(cutoff) => (1 << 6) | (cutoff & 0x0F),
+            None => 0
+        }
+    }
 }
 
+//Magnetometer output data rate. The discriminants are the DO bits of CTRL_REG1_M
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MagOdr {
+    Hz0_625 = 0,
+    Hz1_25 = 1,
+    Hz2_5 = 2,
+    Hz5 = 3,
+    Hz10 = 4,
+    Hz20 = 5,
+    Hz40 = 6,
+    Hz80 = 7
+}
+
+//Magnetometer full scale. The discriminants are the FS bits of CTRL_REG2_M
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MagFullScale {
+    Gauss4 = 0,
+    Gauss8 = 1,
+    Gauss12 = 2,
+    Gauss16 = 3
+}
+
+impl MagFullScale {
+    //Magnetic sensitivity in gauss/LSB. Refer to table 3 in the datasheet
+    pub fn sensitivity(&self) -> f32 {
+        match self {
+            MagFullScale::Gauss4 => 0.00014,
+            MagFullScale::Gauss8 => 0.00029,
+            MagFullScale::Gauss12 => 0.00043,
+            MagFullScale::Gauss16 => 0.00058
+        }
+    }
+}
+
+//Operating mode of an axis pair, trading power for noise. Used for both the OM (X and Y) and OMZ (Z) bits
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MagPerformance {
+    LowPower = 0,
+    Medium = 1,
+    High = 2,
+    UltraHigh = 3
+}
+
+//The discriminants are the MD bits of CTRL_REG3_M
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MagMode {
+    Continuous = 0,
+    Single = 1,
+    PowerDown = 2
+}
+
+pub const GAUSS_TO_MICROTESLA: f32 = 100.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MagnetometerConfig {
+    pub odr: MagOdr,
+    pub full_scale: MagFullScale,
+    pub xy_performance: MagPerformance,
+    pub z_performance: MagPerformance,
+    pub temperature_compensation: bool,
+    pub mode: MagMode
+}
+
+impl Default for MagnetometerConfig {
+    fn default() -> Self {
+        MagnetometerConfig {
+            odr: MagOdr::Hz10,
+            full_scale: MagFullScale::Gauss4,
+            xy_performance: MagPerformance::LowPower,
+            z_performance: MagPerformance::LowPower,
+            temperature_compensation: false,
+            mode: MagMode::Continuous
+        }
+    }
+}
+
+impl MagnetometerConfig {
+    pub fn ctrl_reg1_m(&self) -> u8 {
+        ((self.temperature_compensation as u8) << 7) | ((self.xy_performance as u8) << 5) | ((self.odr as u8) << 2)
+    }
+
+    pub fn ctrl_reg2_m(&self) -> u8 {
+        (self.full_scale as u8) << 5
+    }
+
+    pub fn ctrl_reg3_m(&self) -> u8 {
+        self.mode as u8
+    }
+
+    pub fn ctrl_reg4_m(&self) -> u8 {
+        (self.z_performance as u8) << 2
+    }
+
+    pub fn ctrl_reg5_m(&self) -> u8 {
+        0
+    }
+}
+
+//The discriminants are the FMODE bits of FIFO_CTRL. Refer to section 7.35 in the datasheet
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FifoMode {
+    Bypass = 0, //FIFO disabled, the output registers hold the latest sample
+    Fifo = 1, //Stops collecting samples when the FIFO is full
+    ContinuousToFifo = 3, //Continuous until an interrupt event, then FIFO mode
+    BypassToContinuous = 4, //Bypass until an interrupt event, then continuous mode
+    Continuous = 6 //The oldest sample is overwritten when the FIFO is full
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FifoConfig {
+    pub mode: FifoMode,
+    pub threshold: u8 //FIFO_SRC reports the threshold as reached once the number of stored samples is equal to or higher than this (0 - 31)
+}
+
+impl Default for FifoConfig {
+    fn default() -> Self {
+        FifoConfig {
+            mode: FifoMode::Bypass,
+            threshold: 0
+        }
+    }
+}
+
+impl FifoConfig {
+    pub fn fifo_ctrl(&self) -> u8 {
+        ((self.mode as u8) << 5) | (self.threshold & 0x1F)
+    }
+}
+
+pub const FIFO_SIZE: usize = 32;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FifoStatus {
+    pub samples: u8, //Unread samples
+    pub threshold_reached: bool,
+    pub overrun: bool //Samples were overwritten, or dropped in FIFO mode, since the FIFO was full
+}
+
+//One accelerometer and gyroscope sample read from the FIFO
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct FifoSample {
+    pub timestamp_us: u32, //Estimated from the time of the read and the output data rate
+    pub acceleration: (f32, f32, f32), //g
+    pub gyro: (f32, f32, f32) //dps
+}
+
+//All nine axes and the die temperature, read together by read_all
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct ImuSample {
+    pub timestamp_us: u32, //Time of the read, as passed to read_all
+    pub acceleration: (f32, f32, f32), //g
+    pub gyro: (f32, f32, f32), //dps
+    pub magnetometer: (f32, f32, f32), //gauss
+    pub temperature: f32 //°C
+}
+
+//Latest data read by read_all. None until the first successful read
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
 pub struct ImuData {
-    pub acceleration: ImuAccelerationData
+    pub acceleration: ImuAccelerationData,
+    pub sample: Option<ImuSample>
 }
 
 impl ImuData {
     pub fn new() -> Self {
         ImuData {
             acceleration: ImuAccelerationData {
-                x: 0,
-                y: 0,
-                z: 0
-            }
+                x: 0.0,
+                y: 0.0,
+                z: 0.0
+            },
+            sample: None
         }
     }
 }
 
-pub struct LSM9DS1<'a, T> where T: I2cInstance {
-    pub m_addr: u8, //Magnetometer address
-    pub addr: u8, //Accelerometer and Gyroscope address
-    pub i2c: &'a mut I2c<T>, //Allows for the BMP180 struct to not take ownership of the I2C instance, which means multiple devices can be on the same bus :)
+pub struct LSM9DS1<IF> {
+    pub interface: IF, //I2cInterface or SpiInterface
     pub state: SensorState,
     pub data: ImuData,
-    pub calibration_info: CalibrationInfo
+    pub calibration_info: CalibrationInfo,
+    pub accelerometer_config: AccelerometerConfig, //Applied by boot_accelerometer, or immediately by configure_accelerometer
+    pub gyro_config: GyroConfig, //Applied by boot_gyroscope, or immediately by configure_gyroscope
+    pub magnetometer_config: MagnetometerConfig //Applied by boot_magnetometer, or immediately by configure_magnetometer
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
 pub struct CalibrationInfo {
-    pub magnetometer: MagnetometerCalibration
+    pub magnetometer: MagnetometerCalibration,
+    pub gyroscope: GyroCalibration,
+    pub accelerometer: AccelerometerCalibration
 }
 
 impl CalibrationInfo {
     pub fn new() -> Self {
         CalibrationInfo {
-            magnetometer: MagnetometerCalibration {
-                x_offset: 0,
-                y_offset: 0,
-                z_offset: 0
-            }
+            magnetometer: MagnetometerCalibration::default(),
+            gyroscope: GyroCalibration::default(),
+            accelerometer: AccelerometerCalibration::default()
         }
     }
 }
 
+/*
+ * Zero-rate level in dps, subtracted from the readings. The bias drifts as the die warms up, so it is modelled as
+ * linear in temperature: bias + slope * (temperature - reference_temperature). The slopes are zero unless a
+ * temperature calibration has been run, in which case no temperature reads are needed.
+ */
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct GyroCalibration {
+    pub x_bias: f32,
+    pub y_bias: f32,
+    pub z_bias: f32,
+    pub x_slope: f32, //dps/°C
+    pub y_slope: f32,
+    pub z_slope: f32,
+    pub reference_temperature: f32 //°C
+}
+
+impl GyroCalibration {
+    pub fn is_temperature_compensated(&self) -> bool {
+        self.x_slope != 0.0 || self.y_slope != 0.0 || self.z_slope != 0.0
+    }
+
+    //Bias in dps at the given die temperature
+    pub fn bias_at(&self, temperature: f32) -> (f32, f32, f32) {
+        let delta = temperature - self.reference_temperature;
+        (self.x_bias + self.x_slope * delta, self.y_bias + self.y_slope * delta, self.z_bias + self.z_slope * delta)
+    }
+}
+
+//OUT_TEMP is 16 LSB/°C, and reads 0 at 25 °C
+pub const TEMPERATURE_SENSITIVITY: f32 = 16.0;
+pub const TEMPERATURE_OFFSET: f32 = 25.0;
+
+pub const GYRO_TEMPERATURE_MIN_SPAN: f32 = 5.0; //Smallest temperature range (°C) a temperature calibration is accepted over
+
+/*
+ * Collects gyro bias estimates at different die temperatures, e.g. while the board warms up at rest, and fits a line
+ * through them by least squares. Only the sums needed for the fit are kept.
+ */
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GyroTemperatureCalibrationSession {
+    pub points: u32,
+    pub min_temperature: f32,
+    pub max_temperature: f32,
+    pub(crate) sums: [f64; 2], //Sums of t and t²
+    pub(crate) bias_sums: [[f64; 2]; 3] //Per axis sums of b and t * b
+}
+
+impl Default for GyroTemperatureCalibrationSession {
+    fn default() -> Self {
+        GyroTemperatureCalibrationSession {
+            points: 0,
+            min_temperature: f32::MAX,
+            max_temperature: f32::MIN,
+            sums: [0.0; 2],
+            bias_sums: [[0.0; 2]; 3]
+        }
+    }
+}
+
+pub const GYRO_CALIBRATION_SAMPLES: u32 = 64;
+pub const GYRO_CALIBRATION_MAX_DEVIATION: f32 = 1.0; //Largest per-axis standard deviation (dps) accepted as the board being at rest
+pub const DATA_READY_POLLS: u32 = 1000; //Status register reads before waiting for new data times out
+
+//New data bits of STATUS_REG
+pub const STATUS_XLDA: u8 = 0b001;
+pub const STATUS_GDA: u8 = 0b010;
+pub const STATUS_TDA: u8 = 0b100;
+
+//Signals routed to the INT1_A/G pin by INT1_CTRL
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Int1Config {
+    pub accelerometer_data_ready: bool,
+    pub gyro_data_ready: bool,
+    pub fifo_threshold: bool,
+    pub fifo_overrun: bool,
+    pub fifo_full: bool,
+    pub accelerometer_event: bool, //Accelerometer interrupt generator, e.g. wake-up on motion
+    pub gyro_event: bool //Gyroscope interrupt generator
+}
+
+impl Int1Config {
+    pub fn int1_ctrl(&self) -> u8 {
+        ((self.gyro_event as u8) << 7)
+            | ((self.accelerometer_event as u8) << 6)
+            | ((self.fifo_full as u8) << 5)
+            | ((self.fifo_overrun as u8) << 4)
+            | ((self.fifo_threshold as u8) << 3)
+            | ((self.gyro_data_ready as u8) << 1)
+            | self.accelerometer_data_ready as u8
+    }
+}
+
+//Signals routed to the INT2_A/G pin by INT2_CTRL
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Int2Config {
+    pub accelerometer_data_ready: bool,
+    pub gyro_data_ready: bool,
+    pub temperature_data_ready: bool,
+    pub fifo_threshold: bool,
+    pub fifo_overrun: bool,
+    pub fifo_full: bool,
+    pub inactivity: bool
+}
+
+impl Int2Config {
+    pub fn int2_ctrl(&self) -> u8 {
+        ((self.inactivity as u8) << 7)
+            | ((self.fifo_full as u8) << 5)
+            | ((self.fifo_overrun as u8) << 4)
+            | ((self.fifo_threshold as u8) << 3)
+            | ((self.temperature_data_ready as u8) << 2)
+            | ((self.gyro_data_ready as u8) << 1)
+            | self.accelerometer_data_ready as u8
+    }
+}
+
+//Magnetic threshold interrupt on the INT_M pin. The DRDY_M pin always signals new magnetometer data
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct MagInterruptConfig {
+    pub x: bool,
+    pub y: bool,
+    pub z: bool,
+    pub threshold: u16, //Absolute threshold in LSB (0 - 32767), compared against the output of each enabled axis
+    pub active_high: bool,
+    pub latched: bool //The interrupt stays active until read_magnetometer_interrupt_source is called
+}
+
+impl MagInterruptConfig {
+    pub fn int_cfg_m(&self) -> u8 {
+        let enabled = self.x || self.y || self.z;
+        ((self.x as u8) << 7)
+            | ((self.y as u8) << 6)
+            | ((self.z as u8) << 5)
+            | ((self.active_high as u8) << 2)
+            | ((!self.latched as u8) << 1)
+            | enabled as u8
+    }
+}
+
+//High and low events on each axis. INT_GEN_CFG_XL/INT_GEN_CFG_G and INT_GEN_SRC_XL/INT_GEN_SRC_G share this bit layout
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct AxisEvents {
+    pub x_low: bool,
+    pub x_high: bool,
+    pub y_low: bool,
+    pub y_high: bool,
+    pub z_low: bool,
+    pub z_high: bool
+}
+
+impl AxisEvents {
+    pub const ALL_LOW: AxisEvents = AxisEvents { x_low: true, x_high: false, y_low: true, y_high: false, z_low: true, z_high: false };
+    pub const ALL_HIGH: AxisEvents = AxisEvents { x_low: false, x_high: true, y_low: false, y_high: true, z_low: false, z_high: true };
+    pub const ALL: AxisEvents = AxisEvents { x_low: true, x_high: true, y_low: true, y_high: true, z_low: true, z_high: true };
+
+    pub fn bits(&self) -> u8 {
+        ((self.z_high as u8) << 5)
+            | ((self.z_low as u8) << 4)
+            | ((self.y_high as u8) << 3)
+            | ((self.y_low as u8) << 2)
+            | ((self.x_high as u8) << 1)
+            | self.x_low as u8
+    }
+
+    pub fn from_bits(bits: u8) -> Self {
+        AxisEvents {
+            x_low: bits & 0x01 != 0,
+            x_high: bits & 0x02 != 0,
+            y_low: bits & 0x04 != 0,
+            y_high: bits & 0x08 != 0,
+            z_low: bits & 0x10 != 0,
+            z_high: bits & 0x20 != 0
+        }
+    }
+}
+
+//How the enabled axis events are combined into the interrupt (AOI bit)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EventCombination {
+    Or = 0, //Any enabled event
+    And = 1 //All enabled events at once
+}
+
+//Accelerometer interrupt generator. Route it to a pin with Int1Config::accelerometer_event
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AccelerometerEventConfig {
+    pub events: AxisEvents,
+    pub combination: EventCombination,
+    pub six_d: bool, //6D detection. With Or an event is raised when the orientation changes, with And while the orientation is held
+    pub threshold: (f32, f32, f32), //Per-axis threshold in g, in steps of 1/256 of the full scale
+    pub duration: u8, //Samples the condition must hold for before the event is raised (0 - 127)
+    pub wait: bool, //Also wait duration samples before clearing the event
+    pub latched: bool //The event stays set until read_accelerometer_events is called
+}
+
+impl Default for AccelerometerEventConfig {
+    fn default() -> Self {
+        AccelerometerEventConfig {
+            events: AxisEvents::default(),
+            combination: EventCombination::Or,
+            six_d: false,
+            threshold: (0.0, 0.0, 0.0),
+            duration: 0,
+            wait: false,
+            latched: false
+        }
+    }
+}
+
+impl AccelerometerEventConfig {
+    //Free-fall: all three axes below threshold (typically 0.3 - 0.5 g) at once
+    pub fn free_fall(threshold: f32, duration: u8) -> Self {
+        AccelerometerEventConfig {
+            events: AxisEvents::ALL_LOW,
+            combination: EventCombination::And,
+            threshold: (threshold, threshold, threshold),
+            duration,
+            latched: true,
+            ..AccelerometerEventConfig::default()
+        }
+    }
+
+    //Wake-up: any axis above threshold
+    pub fn wake_up(threshold: f32, duration: u8) -> Self {
+        AccelerometerEventConfig {
+            events: AxisEvents::ALL_HIGH,
+            threshold: (threshold, threshold, threshold),
+            duration,
+            latched: true,
+            ..AccelerometerEventConfig::default()
+        }
+    }
+
+    //6D orientation: the source reports which axis is pointing up (high) or down (low) past threshold
+    pub fn orientation(threshold: f32, duration: u8) -> Self {
+        AccelerometerEventConfig {
+            events: AxisEvents::ALL,
+            combination: EventCombination::And,
+            six_d: true,
+            threshold: (threshold, threshold, threshold),
+            duration,
+            ..AccelerometerEventConfig::default()
+        }
+    }
+
+    pub fn int_gen_cfg_xl(&self) -> u8 {
+        ((self.combination as u8) << 7) | ((self.six_d as u8) << 6) | self.events.bits()
+    }
+
+    pub fn int_gen_dur_xl(&self) -> u8 {
+        ((self.wait as u8) << 7) | (self.duration & 0x7F)
+    }
+}
+
+//Gyroscope interrupt generator. Route it to a pin with Int1Config::gyro_event
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GyroEventConfig {
+    pub events: AxisEvents,
+    pub combination: EventCombination,
+    pub threshold: (f32, f32, f32), //Per-axis threshold in dps
+    pub duration: u8, //Samples the condition must hold for before the event is raised (0 - 127)
+    pub wait: bool, //Also wait duration samples before clearing the event
+    pub decrement: bool, //The duration counter counts down instead of resetting when the condition stops holding (DCRM_G)
+    pub latched: bool //The event stays set until read_gyro_events is called
+}
+
+impl Default for GyroEventConfig {
+    fn default() -> Self {
+        GyroEventConfig {
+            events: AxisEvents::default(),
+            combination: EventCombination::Or,
+            threshold: (0.0, 0.0, 0.0),
+            duration: 0,
+            wait: false,
+            decrement: false,
+            latched: false
+        }
+    }
+}
+
+impl GyroEventConfig {
+    //Any axis rotating faster than threshold
+    pub fn rotation(threshold: f32, duration: u8) -> Self {
+        GyroEventConfig {
+            events: AxisEvents::ALL_HIGH,
+            threshold: (threshold, threshold, threshold),
+            duration,
+            latched: true,
+            ..GyroEventConfig::default()
+        }
+    }
+
+    pub fn int_gen_cfg_g(&self) -> u8 {
+        ((self.combination as u8) << 7) | ((self.latched as u8) << 6) | self.events.bits()
+    }
+
+    pub fn int_gen_dur_g(&self) -> u8 {
+        ((self.wait as u8) << 7) | (self.duration & 0x7F)
+    }
+}
+
+//Decoded INT_GEN_SRC_XL/INT_GEN_SRC_G. Reading it clears a latched event
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct InertialEvents {
+    pub events: AxisEvents,
+    pub active: bool //The generator's event condition is met
+}
+
+//Decoded STATUS_REG
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ImuStatus {
+    pub accelerometer_data: bool, //New data is available
+    pub gyro_data: bool,
+    pub temperature_data: bool,
+    pub accelerometer_event: bool, //The interrupt generators have fired
+    pub gyro_event: bool,
+    pub inactivity: bool
+}
+
+//Decoded STATUS_REG_M
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MagnetometerStatus {
+    pub data: bool, //New data is available on all three axes
+    pub overrun: bool //New data overwrote a sample before it was read
+}
+
+//Decoded INT_SRC_M. Reading it clears a latched interrupt
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MagInterruptSource {
+    pub positive: (bool, bool, bool), //Axes above the positive threshold
+    pub negative: (bool, bool, bool), //Axes below the negative threshold
+    pub active: bool
+}
+
+//Per-axis correction of the acceleration in g, applied as (reading - offset) * scale
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AccelerometerCalibration {
+    pub x_offset: f32,
+    pub y_offset: f32,
+    pub z_offset: f32,
+    pub x_scale: f32,
+    pub y_scale: f32,
+    pub z_scale: f32
+}
+
+impl Default for AccelerometerCalibration {
+    fn default() -> Self {
+        AccelerometerCalibration {
+            x_offset: 0.0,
+            y_offset: 0.0,
+            z_offset: 0.0,
+            x_scale: 1.0,
+            y_scale: 1.0,
+            z_scale: 1.0
+        }
+    }
+}
+
+//Orientations of the six-position calibration, named after the axis pointing up (away from the ground)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AccelerometerPosition {
+    XUp = 0,
+    XDown = 1,
+    YUp = 2,
+    YDown = 3,
+    ZUp = 4,
+    ZDown = 5
+}
+
+//The mean reading of the vertical axis in each of the six positions, in g
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct AccelerometerCalibrationSession {
+    pub readings: [Option<f32>; 6]
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct MagnetometerCalibration {
+    //Hard-iron offsets in LSB, subtracted from the raw readings
     pub x_offset: i32,
     pub y_offset: i32,
-    pub z_offset: i32
+    pub z_offset: i32,
+    pub soft_iron: [[f32; 3]; 3] //Soft-iron correction, multiplied with the offset-corrected readings
+}
+
+impl Default for MagnetometerCalibration {
+    fn default() -> Self {
+        MagnetometerCalibration {
+            x_offset: 0,
+            y_offset: 0,
+            z_offset: 0,
+            soft_iron: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]
+        }
+    }
+}
+
+//Samples needed before a magnetometer calibration session can be finished
+pub const MAG_CALIBRATION_MIN_SAMPLES: u32 = 32;
+
+/*
+ * Accumulates raw magnetometer readings while the board is rotated through as many orientations as possible.
+ * Only the per-axis extremes and the sums needed for the ellipsoid fit are kept, so no samples are stored.
+ */
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MagnetometerCalibrationSession {
+    pub min: [i16; 3],
+    pub max: [i16; 3],
+    pub samples: u32,
+    pub(crate) moments: [[f64; 10]; 10] //Sums of the products of (x², y², z², 2xy, 2xz, 2yz, 2x, 2y, 2z, 1), with readings normalised to ±1
+}
+
+impl Default for MagnetometerCalibrationSession {
+    fn default() -> Self {
+        MagnetometerCalibrationSession {
+            min: [i16::MAX; 3],
+            max: [i16::MIN; 3],
+            samples: 0,
+            moments: [[0.0; 10]; 10]
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MagnetometerCalibrationResult {
+    pub calibration: MagnetometerCalibration,
+    pub fit_error: f32 //RMS deviation of the corrected field magnitude, relative to its mean. 0 means every sample lies on a sphere
 }
 
 pub enum MagnetometerRM {
@@ -102,11 +861,16 @@ pub enum MagnetometerRM {
     OutYLM = 0x2A,
     OutYHM = 0x2B,
     OutZLM = 0x2C,
-    OutZHM = 0x2D
+    OutZHM = 0x2D,
+    IntCfgM = 0x30,
+    IntSrcM = 0x31,
+    IntThsLM = 0x32,
+    IntThsHM = 0x33
 }
 
 pub enum AccelerometerRM {
     CtrlReg6Xl = 0x20,
+    CtrlReg7Xl = 0x21,
     OutXXlL = 0x28,
     OutXXlH = 0x29,
     OutYXlL = 0x2A,
@@ -115,8 +879,43 @@ pub enum AccelerometerRM {
     OutZXlH = 0x2D
 }
 
+pub enum InterruptRM {
+    Int1Ctrl = 0x0C,
+    Int2Ctrl = 0x0D
+}
+
+pub enum EventRM {
+    IntGenCfgXl = 0x06,
+    IntGenThsXXl = 0x07,
+    IntGenThsYXl = 0x08,
+    IntGenThsZXl = 0x09,
+    IntGenDurXl = 0x0A,
+    IntGenSrcG = 0x14,
+    CtrlReg4 = 0x1E,
+    IntGenSrcXl = 0x26,
+    IntGenCfgG = 0x30,
+    IntGenThsXHG = 0x31,
+    IntGenThsXLG = 0x32,
+    IntGenThsYHG = 0x33,
+    IntGenThsYLG = 0x34,
+    IntGenThsZHG = 0x35,
+    IntGenThsZLG = 0x36,
+    IntGenDurG = 0x37
+}
+
+pub enum FifoRM {
+    CtrlReg9 = 0x23,
+    FifoCtrl = 0x2E,
+    FifoSrc = 0x2F
+}
+
 pub enum GyroRM {
     CtrlReg1G = 0x10,
+    CtrlReg2G = 0x11,
+    CtrlReg3G = 0x12,
+    OutTempL = 0x15,
+    OutTempH = 0x16,
+    StatusReg = 0x17,
     OutXGL = 0x18,
     OutXGH = 0x19,
     OutYGL = 0x1A,
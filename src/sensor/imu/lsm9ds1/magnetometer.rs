@@ -1,98 +1,355 @@
+use crate::sensor::{SensorError, SensorState};
 use crate::sensor::imu::Magnetometer;
-use cortex_m::asm::nop;
-use stm32f4xx_hal::i2c::Instance as I2cInstance;
-use super::lsm9ds1_s::{MagnetometerRM, LSM9DS1};
-
-pub enum Axis {
-    X,
-    Y,
-    Z
+use core::fmt::Debug;
+use super::interface::{Device, RegisterInterface};
+use micromath::F32Ext;
+use super::lsm9ds1_s::{MagnetometerCalibration, MagnetometerCalibrationResult, MagnetometerCalibrationSession, MagnetometerConfig, MagnetometerRM, LSM9DS1, MAG_CALIBRATION_MIN_SAMPLES};
+
+const MAG_NORMALISATION: f64 = 32768.0;
+const MAG_SQUARE_ROOT_ITERATIONS: u32 = 64;
+
+type Matrix3 = [[f64; 3]; 3];
+
+impl MagnetometerCalibrationSession {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_sample(&mut self, raw: (i16, i16, i16)) {
+        let raw = [raw.0, raw.1, raw.2];
+        for (axis, value) in raw.iter().enumerate() {
+            self.min[axis] = self.min[axis].min(*value);
+            self.max[axis] = self.max[axis].max(*value);
+        }
+
+        let [x, y, z] = raw.map(|value| value as f64 / MAG_NORMALISATION);
+        let features = [x * x, y * y, z * z, 2.0 * x * y, 2.0 * x * z, 2.0 * y * z, 2.0 * x, 2.0 * y, 2.0 * z, 1.0];
+        for (row, a) in self.moments.iter_mut().zip(features) {
+            for (moment, b) in row.iter_mut().zip(features) {
+                *moment += a * b;
+            }
+        }
+
+        self.samples += 1;
+    }
+
+    /*
+     * Computes the calibration from the accumulated samples by fitting the ellipsoid x'Ax + 2b'x = 1 in a least squares sense.
+     * The hard-iron offsets are the centre of the ellipsoid, and the soft-iron matrix is the symmetric matrix that maps it
+     * onto a sphere with the mean radius of the ellipsoid, so that a tilted ellipsoid is corrected as well.
+     */
+    pub fn finish(&self) -> Result<MagnetometerCalibrationResult, SensorError> {
+        if self.samples < MAG_CALIBRATION_MIN_SAMPLES {
+            return Err(SensorError::FailedToCalibrate);
+        }
+
+        for axis in 0..3 {
+            if self.max[axis] <= self.min[axis] {
+                return Err(SensorError::FailedToCalibrate); //The board was not rotated around this axis
+            }
+        }
+
+        //Normal equations of the fit. The constant feature is the right-hand side
+        let mut normal = [[0f64; 9]; 9];
+        let mut rhs = [0f64; 9];
+        for row in 0..9 {
+            normal[row].copy_from_slice(&self.moments[row][..9]);
+            rhs[row] = self.moments[row][9];
+        }
+        let [a, b, c, d, e, f, g, h, i] = solve_linear(normal, rhs).ok_or(SensorError::FailedToCalibrate)?;
+        let quadric = [[a, d, e], [d, b, f], [e, f, c]];
+
+        //Moving the origin to the centre turns the fit into (x - centre)'Q(x - centre) = 1
+        let inverse = invert_3x3(&quadric).ok_or(SensorError::FailedToCalibrate)?;
+        let centre = multiply_3x3(&inverse, &[g, h, i]).map(|value| -value);
+        let k = 1.0 + dot(&centre, &multiply_3x3(&quadric, &centre));
+        let q = quadric.map(|row| row.map(|value| value / k));
+        if !is_positive_definite(&q) {
+            return Err(SensorError::FailedToCalibrate); //The samples do not lie on an ellipsoid
+        }
+
+        //The radii along the axes of the ellipsoid are the eigenvalues of Q^-1/2
+        let (root, inverse_root) = square_root_3x3(&q).ok_or(SensorError::FailedToCalibrate)?;
+        let mean_radius = (inverse_root[0][0] + inverse_root[1][1] + inverse_root[2][2]) / 3.0;
+        let soft_iron = root.map(|row| row.map(|value| value * mean_radius));
+
+        let offsets = centre.map(|value| ((value * MAG_NORMALISATION) as f32).round() as i32);
+
+        //The squared corrected magnitude |W(x - offset)|² is a linear combination of the accumulated features
+        let offset = offsets.map(|value| value as f64 / MAG_NORMALISATION);
+        let p = multiply_matrices_3x3(&soft_iron, &soft_iron);
+        let p_offset = multiply_3x3(&p, &offset);
+        let weights = [
+            p[0][0], p[1][1], p[2][2], p[0][1], p[0][2], p[1][2],
+            -p_offset[0], -p_offset[1], -p_offset[2], dot(&offset, &p_offset)
+        ];
+
+        let samples = self.samples as f64;
+        let mut mean = 0.0;
+        let mut mean_square = 0.0;
+        for (row, a) in self.moments.iter().zip(weights) {
+            mean += a * row[9];
+            for (moment, b) in row.iter().zip(weights) {
+                mean_square += a * b * moment;
+            }
+        }
+        mean /= samples;
+        mean_square /= samples;
+
+        //The relative deviation of the magnitude is half that of the squared magnitude
+        let variance = (mean_square - mean * mean).max(0.0);
+        let fit_error = (variance as f32).sqrt() / (mean as f32) / 2.0;
+
+        Ok(MagnetometerCalibrationResult {
+            calibration: MagnetometerCalibration {
+                x_offset: offsets[0],
+                y_offset: offsets[1],
+                z_offset: offsets[2],
+                soft_iron: soft_iron.map(|row| row.map(|value| value as f32))
+            },
+            fit_error
+        })
+    }
+}
+
+//Solves matrix * x = rhs by Gaussian elimination with partial pivoting. Returns None if the matrix is singular
+fn solve_linear<const N: usize>(mut matrix: [[f64; N]; N], mut rhs: [f64; N]) -> Option<[f64; N]> {
+    for column in 0..N {
+        let pivot = (column..N).max_by(|&a, &b| matrix[a][column].abs().total_cmp(&matrix[b][column].abs()))?;
+        if matrix[pivot][column] == 0.0 {
+            return None;
+        }
+        matrix.swap(column, pivot);
+        rhs.swap(column, pivot);
+
+        let (upper, lower) = matrix.split_at_mut(column + 1);
+        let pivot_row = &upper[column];
+        for (row, values) in lower.iter_mut().enumerate() {
+            let factor = values[column] / pivot_row[column];
+            for (value, pivot_value) in values[column..].iter_mut().zip(&pivot_row[column..]) {
+                *value -= factor * pivot_value;
+            }
+            rhs[column + 1 + row] -= factor * rhs[column];
+        }
+    }
+
+    let mut x = [0f64; N];
+    for row in (0..N).rev() {
+        let sum: f64 = (row + 1..N).map(|k| matrix[row][k] * x[k]).sum();
+        x[row] = (rhs[row] - sum) / matrix[row][row];
+    }
+    Some(x)
+}
+
+fn dot(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn multiply_3x3(matrix: &Matrix3, vector: &[f64; 3]) -> [f64; 3] {
+    matrix.map(|row| dot(&row, vector))
+}
+
+fn multiply_matrices_3x3(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    let mut product: Matrix3 = [[0.0; 3]; 3];
+    for row in 0..3 {
+        for column in 0..3 {
+            product[row][column] = (0..3).map(|k| a[row][k] * b[k][column]).sum();
+        }
+    }
+    product
+}
+
+fn invert_3x3(m: &Matrix3) -> Option<Matrix3> {
+    let cofactors = [
+        [m[1][1] * m[2][2] - m[1][2] * m[2][1], m[1][2] * m[2][0] - m[1][0] * m[2][2], m[1][0] * m[2][1] - m[1][1] * m[2][0]],
+        [m[0][2] * m[2][1] - m[0][1] * m[2][2], m[0][0] * m[2][2] - m[0][2] * m[2][0], m[0][1] * m[2][0] - m[0][0] * m[2][1]],
+        [m[0][1] * m[1][2] - m[0][2] * m[1][1], m[0][2] * m[1][0] - m[0][0] * m[1][2], m[0][0] * m[1][1] - m[0][1] * m[1][0]]
+    ];
+    let determinant = dot(&m[0], &[cofactors[0][0], cofactors[0][1], cofactors[0][2]]);
+    if determinant == 0.0 {
+        return None;
+    }
+
+    //The inverse is the transposed cofactor matrix over the determinant
+    let mut inverse: Matrix3 = [[0.0; 3]; 3];
+    for row in 0..3 {
+        for column in 0..3 {
+            inverse[row][column] = cofactors[column][row] / determinant;
+        }
+    }
+    Some(inverse)
+}
+
+//Sylvester's criterion: every leading principal minor is positive
+fn is_positive_definite(m: &Matrix3) -> bool {
+    let minor_2 = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+    let minor_3 = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+    m[0][0] > 0.0 && minor_2 > 0.0 && minor_3 > 0.0
+}
+
+//Square root of a positive definite matrix and its inverse, by the Denman-Beavers iteration
+fn square_root_3x3(m: &Matrix3) -> Option<(Matrix3, Matrix3)> {
+    let mut root = *m;
+    let mut inverse_root = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    for _ in 0..MAG_SQUARE_ROOT_ITERATIONS {
+        let root_inverse = invert_3x3(&root)?;
+        let inverse_root_inverse = invert_3x3(&inverse_root)?;
+        let mut change = 0.0;
+        for row in 0..3 {
+            for column in 0..3 {
+                let next = (root[row][column] + inverse_root_inverse[row][column]) / 2.0;
+                change += (next - root[row][column]).abs();
+                root[row][column] = next;
+                inverse_root[row][column] = (inverse_root[row][column] + root_inverse[row][column]) / 2.0;
+            }
+        }
+
+        if change <= 1e-12 * (root[0][0] + root[1][1] + root[2][2]) {
+            return Some((root, inverse_root));
+        }
+    }
+    None
 }
 
-impl<'a, T> LSM9DS1<'a, T> where T: I2cInstance {
-    pub fn boot_magnetometer(&mut self) -> Result<(), ()> {
-        let initial_value = self.read_ctrl_reg3_m();
-        let mut mode: u8 = 0x0; //Continuous mode. Refer to table 117 in the datasheet
-        let res = self.i2c.write(self.m_addr, &[MagnetometerRM::CtrlReg3M as u8, mode]);
-        let final_value = self.read_ctrl_reg3_m();
+impl<IF, E> LSM9DS1<IF> where IF: RegisterInterface<Error = E>, E: Debug {
+    pub fn boot_magnetometer(&mut self) -> Result<(), SensorError<E>> {
+        self.configure_magnetometer(self.magnetometer_config)
+    }
 
-        if initial_value != final_value && final_value == mode {
+    //Writes the configuration to CTRL_REG1_M - CTRL_REG5_M. Readings are scaled using the new full scale from then on
+    pub fn configure_magnetometer(&mut self, config: MagnetometerConfig) -> Result<(), SensorError<E>> {
+        let registers = [
+            (MagnetometerRM::CtrlReg1M, config.ctrl_reg1_m()),
+            (MagnetometerRM::CtrlReg2M, config.ctrl_reg2_m()),
+            (MagnetometerRM::CtrlReg4M, config.ctrl_reg4_m()),
+            (MagnetometerRM::CtrlReg5M, config.ctrl_reg5_m()),
+            (MagnetometerRM::CtrlReg3M, config.ctrl_reg3_m() | IF::CTRL_REG3_M) //Written last, as it starts the conversions
+        ];
+
+        for (register, value) in registers {
+            self.interface.write_registers(Device::Magnetometer, register as u8, &[value]).map_err(|e| self.state.bus_error(e))?;
+        }
+        let final_value = self.read_ctrl_reg3_m()?;
+
+        if final_value == config.ctrl_reg3_m() | IF::CTRL_REG3_M {
+            self.magnetometer_config = config;
             Ok(())
         } else {
-            Err(())
+            self.state = SensorState::ERROR(SensorError::InvalidConfiguration);
+            Err(SensorError::InvalidConfiguration)
         }
     }
     
-    fn read_ctrl_reg3_m (&mut self) -> u8 {
+    fn read_ctrl_reg3_m (&mut self) -> Result<u8, SensorError<E>> {
         let mut rx_dat: [u8; 1] = [0; 1];
-        let res = self.i2c.write_read(self.m_addr, &[MagnetometerRM::CtrlReg3M as u8], &mut rx_dat);
-        rx_dat[0]
+        self.interface.read_registers(Device::Magnetometer, MagnetometerRM::CtrlReg3M as u8, &mut rx_dat).map_err(|e| self.state.bus_error(e))?;
+        Ok(rx_dat[0])
     }
 
-    pub fn calibrate_magnetometer(&mut self) {
-        let mut rx_buffer: [u8; 6] = [0; 6]; //The magnetometer has 6 registers that need to be read to get the calibration data, 2 for each axis
-        let res = self.i2c.write_read(self.m_addr, &[MagnetometerRM::OutXLM as u8], &mut rx_buffer);
-
-        let xl = rx_buffer[0];
-        let xh = rx_buffer[1];
-        let yl = rx_buffer[2];
-        let yh = rx_buffer[3];
-        let zl = rx_buffer[4];
-        let zh = rx_buffer[5];
+    //Reads the raw output of all three axes in a single burst, in LSB
+    pub fn read_raw_magnetometer(&mut self) -> Result<(i16, i16, i16), SensorError<E>> {
+        let mut rx_buffer: [u8; 6] = [0; 6];
+        self.interface.read_registers(Device::Magnetometer, MagnetometerRM::OutXLM as u8, &mut rx_buffer).map_err(|e| self.state.bus_error(e))?;
 
-        let x_offset = self.twos_complement(xh, xl);
-        let y_offset = self.twos_complement(yh, yl);
-        let z_offset = self.twos_complement(zh, zl);
+        //Incoming data is little-endian by default
+        let x = self.twos_complement(rx_buffer[1], rx_buffer[0]);
+        let y = self.twos_complement(rx_buffer[3], rx_buffer[2]);
+        let z = self.twos_complement(rx_buffer[5], rx_buffer[4]);
 
-        self.calibration_info.magnetometer.x_offset = x_offset as i32;
-        self.calibration_info.magnetometer.y_offset = y_offset as i32;
-        self.calibration_info.magnetometer.z_offset = z_offset as i32;
+        Ok((x, y, z))
     }
 
-    pub fn read_raw_magnetometer_axis(&mut self, axis: Axis) -> i32 {
-        let range = 4; //4 gauss full range
+    //Adds the current reading to a calibration session
+    pub fn sample_magnetometer_calibration(&mut self, session: &mut MagnetometerCalibrationSession) -> Result<(), SensorError<E>> {
+        let raw = self.read_raw_magnetometer()?;
+        session.add_sample(raw);
+        Ok(())
+    }
 
-        let mut rx_buffer: [u8; 2] = [0; 2];
-        let mut addr: u8 = match axis {
-            Axis::X => MagnetometerRM::OutXLM as u8,
-            Axis::Y => MagnetometerRM::OutYLM as u8,
-            Axis::Z => MagnetometerRM::OutZLM as u8
+    /*
+     * Finishes a calibration session and applies the result to calibration_info.
+     * With write_offset_registers, the hard-iron offsets are added to OFFSET_X/Y/Z_REG_M so that the sensor removes them
+     * itself, and the offsets kept by the driver are zeroed. Otherwise the registers are left untouched.
+     */
+    pub fn calibrate_magnetometer(&mut self, session: &MagnetometerCalibrationSession, write_offset_registers: bool) -> Result<MagnetometerCalibrationResult, SensorError<E>> {
+        let mut result = match session.finish() {
+            Ok(result) => result,
+            Err(_) => {
+                self.state = SensorState::ERROR(SensorError::FailedToCalibrate);
+                return Err(SensorError::FailedToCalibrate);
+            }
         };
 
-        //Incoming data is little-endian by default
-        let res = self.i2c.write_read(self.m_addr, &[addr], &mut rx_buffer);
-        let high = rx_buffer[1];
-        let low = rx_buffer[0];
-        let result = self.twos_complement(high, low);
-
-        let correction_value = match axis {
-            Axis::X => self.calibration_info.magnetometer.x_offset,
-            Axis::Y => self.calibration_info.magnetometer.y_offset,
-            Axis::Z => self.calibration_info.magnetometer.z_offset
-        };
+        if write_offset_registers {
+            //The session saw readings with the current register offsets already removed
+            let (x, y, z) = self.read_magnetometer_offset_registers()?;
+            let offsets = [
+                x as i32 + result.calibration.x_offset,
+                y as i32 + result.calibration.y_offset,
+                z as i32 + result.calibration.z_offset
+            ].map(|offset| offset.clamp(i16::MIN as i32, i16::MAX as i32) as i16);
+
+            let mut tx_buffer: [u8; 6] = [0; 6];
+            for (bytes, offset) in tx_buffer.chunks_mut(2).zip(offsets) {
+                bytes.copy_from_slice(&offset.to_le_bytes());
+            }
+            self.interface.write_registers(Device::Magnetometer, MagnetometerRM::OffsetXRegLM as u8, &tx_buffer).map_err(|e| self.state.bus_error(e))?;
 
-        (result as i32 /*- correction_value*/) / range
+            result.calibration.x_offset = 0;
+            result.calibration.y_offset = 0;
+            result.calibration.z_offset = 0;
+        }
+
+        self.calibration_info.magnetometer = result.calibration;
+        Ok(result)
     }
 
-    pub fn read_magnetometer_x(&mut self) -> i32 {
-        self.read_raw_magnetometer_axis(Axis::X)
+    fn read_magnetometer_offset_registers(&mut self) -> Result<(i16, i16, i16), SensorError<E>> {
+        let mut rx_buffer: [u8; 6] = [0; 6];
+        self.interface.read_registers(Device::Magnetometer, MagnetometerRM::OffsetXRegLM as u8, &mut rx_buffer).map_err(|e| self.state.bus_error(e))?;
+
+        let x = self.twos_complement(rx_buffer[1], rx_buffer[0]);
+        let y = self.twos_complement(rx_buffer[3], rx_buffer[2]);
+        let z = self.twos_complement(rx_buffer[5], rx_buffer[4]);
+
+        Ok((x, y, z))
     }
 
-    pub fn read_magnetometer_y(&mut self) -> i32 {
-        self.read_raw_magnetometer_axis(Axis::Y)
+    pub fn read_magnetometer_x(&mut self) -> Result<f32, SensorError<E>> {
+        Ok(self.read_magnetometer()?.0)
     }
 
-    pub fn read_magnetometer_z(&mut self) -> i32 {
-        self.read_raw_magnetometer_axis(Axis::X)
+    pub fn read_magnetometer_y(&mut self) -> Result<f32, SensorError<E>> {
+        Ok(self.read_magnetometer()?.1)
     }
-}
 
-impl<'a, T> Magnetometer for LSM9DS1<'a, T> where T: I2cInstance {
-    fn read_magnetometer(&mut self) -> (i32, i32, i32) {
-        let x = self.read_magnetometer_x();
-        let y = self.read_magnetometer_y();
-        let z = self.read_magnetometer_z();
+    pub fn read_magnetometer_z(&mut self) -> Result<f32, SensorError<E>> {
+        Ok(self.read_magnetometer()?.2)
+    }
+
+    //Converts a raw reading to gauss, removing the hard-iron offsets and then applying the soft-iron matrix
+    pub(crate) fn scale_magnetometer(&self, raw: (i16, i16, i16)) -> (f32, f32, f32) {
+        let (x, y, z) = raw;
+        let calibration = &self.calibration_info.magnetometer;
+        let sensitivity = self.magnetometer_config.full_scale.sensitivity();
+
+        let field = [
+            (x as i32 - calibration.x_offset) as f32 * sensitivity,
+            (y as i32 - calibration.y_offset) as f32 * sensitivity,
+            (z as i32 - calibration.z_offset) as f32 * sensitivity
+        ];
+        let [cx, cy, cz] = calibration.soft_iron.map(|row| row[0] * field[0] + row[1] * field[1] + row[2] * field[2]);
 
-        (x, y, z)
+        (cx, cy, cz)
     }
-}
\ No newline at end of file
+}
+
+impl<IF, E> Magnetometer for LSM9DS1<IF> where IF: RegisterInterface<Error = E>, E: Debug {
+    //Returns the field strength in gauss, corrected with the hard-iron and soft-iron calibration from calibration_info
+    fn read_magnetometer(&mut self) -> Result<(f32, f32, f32), SensorError<E>> {
+        let raw = self.read_raw_magnetometer()?;
+        Ok(self.scale_magnetometer(raw))
+    }
+}
@@ -0,0 +1,45 @@
+use crate::sensor::SensorError;
+use core::fmt::Debug;
+use super::interface::{Device, RegisterInterface};
+use super::lsm9ds1_s::{GyroRM, ImuAccelerationData, ImuSample, LSM9DS1};
+
+impl<IF, E> LSM9DS1<IF> where IF: RegisterInterface<Error = E>, E: Debug {
+    /*
+     * Reads all nine axes and the die temperature in three burst reads, so that they come from the same output
+     * data period, and stores the sample in data. now_us is the current time in µs and is used as the timestamp.
+     *
+     * The accelerometer/gyroscope block is read as OUT_TEMP - OUT_Z_G followed by OUT_X_XL - OUT_Z_XL, rather than as
+     * one burst, as the registers in between include INT_GEN_SRC_XL and reading it would clear latched events.
+     */
+    pub fn read_all(&mut self, now_us: u32) -> Result<ImuSample, SensorError<E>> {
+        //OUT_TEMP_L, OUT_TEMP_H, STATUS_REG, OUT_X_L_G ... OUT_Z_H_G
+        let mut rx_buffer: [u8; 9] = [0; 9];
+        self.interface.read_registers(Device::AccelGyro, GyroRM::OutTempL as u8, &mut rx_buffer).map_err(|e| self.state.bus_error(e))?;
+        let temperature = self.scale_temperature(self.twos_complement(rx_buffer[1], rx_buffer[0]));
+        let gyro = (
+            self.twos_complement(rx_buffer[4], rx_buffer[3]),
+            self.twos_complement(rx_buffer[6], rx_buffer[5]),
+            self.twos_complement(rx_buffer[8], rx_buffer[7])
+        );
+
+        let acceleration = self.read_raw_acceleration()?;
+        let magnetometer = self.read_raw_magnetometer()?;
+
+        let sample = ImuSample {
+            timestamp_us: now_us,
+            acceleration: self.scale_acceleration(acceleration),
+            gyro: self.scale_gyro(gyro, temperature),
+            magnetometer: self.scale_magnetometer(magnetometer),
+            temperature
+        };
+
+        self.data.acceleration = ImuAccelerationData {
+            x: sample.acceleration.0,
+            y: sample.acceleration.1,
+            z: sample.acceleration.2
+        };
+        self.data.sample = Some(sample);
+
+        Ok(sample)
+    }
+}
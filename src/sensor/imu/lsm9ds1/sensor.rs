@@ -1,31 +1,39 @@
-use crate::sensor::{Sensor, SensorState, imu::lsm9ds1::lsm9ds1_s::LSM9DS1};
-use stm32f4xx_hal::i2c::{I2c, Instance as I2cInstance};
+use crate::sensor::{Sensor, SensorError, SensorState, imu::lsm9ds1::lsm9ds1_s::{LSM9DS1, GYRO_CALIBRATION_SAMPLES, GYRO_CALIBRATION_MAX_DEVIATION}};
+use core::fmt::Debug;
+use super::interface::RegisterInterface;
 
-impl <'a, T> Sensor for LSM9DS1<'a, T> where T: I2cInstance {
-    fn init(&mut self) -> Result<(), ()> {
+impl<IF, E> Sensor for LSM9DS1<IF> where IF: RegisterInterface<Error = E>, E: Debug {
+    type BusError = E;
+
+    fn init(&mut self) -> Result<(), SensorError<E>> {
         self.state = SensorState::STARTUP;
-        let is_discovered = self.sanity_check();
-        if !is_discovered {
-            return Err(());
-        }
+        self.sanity_check()?;
 
-        self.boot_magnetometer().unwrap();
-        self.boot_accelerometer().unwrap();
-        self.boot_gyroscope().unwrap();
+        self.boot_magnetometer()?;
+        self.boot_accelerometer()?;
+        self.boot_gyroscope()?;
 
-        self.calibrate()
+        //The gyro bias is not captured here, so a calibration restored by load_calibration is kept. Call calibrate with the board at rest to capture it
+        self.state = SensorState::READY;
+
+        Ok(())
     }
 
-    fn calibrate(&mut self) -> Result<(), ()> {
+    fn calibrate(&mut self) -> Result<(), SensorError<E>> {
         self.state = SensorState::CALIBRATING;
 
-        self.calibrate_magnetometer();
-        self.calibrate_accelerometer();
-        self.calibrate_gyroscope();
-
-        self.state = SensorState::READY;
-
-        Ok(())
+        //The board must be at rest. If it moved, the previous bias is kept and the sensor stays usable
+        match self.calibrate_gyroscope(GYRO_CALIBRATION_SAMPLES, GYRO_CALIBRATION_MAX_DEVIATION) {
+            Ok(_) => {
+                self.state = SensorState::READY;
+                Ok(())
+            },
+            Err(SensorError::FailedToCalibrate) => {
+                self.state = SensorState::READY;
+                Err(SensorError::FailedToCalibrate)
+            },
+            Err(e) => Err(e)
+        }
     }
 
     fn sensor_state(&self) -> &SensorState {
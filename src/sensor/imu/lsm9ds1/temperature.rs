@@ -0,0 +1,19 @@
+use crate::sensor::SensorError;
+use core::fmt::Debug;
+use super::interface::{Device, RegisterInterface};
+use super::lsm9ds1_s::{GyroRM, LSM9DS1, TEMPERATURE_OFFSET, TEMPERATURE_SENSITIVITY};
+
+impl<IF, E> LSM9DS1<IF> where IF: RegisterInterface<Error = E>, E: Debug {
+    //Reads the die temperature in °C. It is updated at the gyroscope ODR, or the accelerometer ODR when the gyroscope is off
+    pub fn read_temperature(&mut self) -> Result<f32, SensorError<E>> {
+        let mut rx_buffer: [u8; 2] = [0; 2];
+        self.interface.read_registers(Device::AccelGyro, GyroRM::OutTempL as u8, &mut rx_buffer).map_err(|e| self.state.bus_error(e))?;
+        let raw = self.twos_complement(rx_buffer[1], rx_buffer[0]);
+
+        Ok(self.scale_temperature(raw))
+    }
+
+    pub(crate) fn scale_temperature(&self, raw: i16) -> f32 {
+        raw as f32 / TEMPERATURE_SENSITIVITY + TEMPERATURE_OFFSET
+    }
+}
@@ -0,0 +1,949 @@
+use crate::mock::{MockError, MockI2c, MockSpi};
+use crate::sensor::imu::{Accelerometer, Gyroscope, Magnetometer};
+use crate::sensor::{Sensor, SensorError, SensorState};
+use crate::sensor::calibration::CalibrationError;
+use super::interface::{Device, I2cInterface, RegisterInterface, SpiInterfaceError};
+use super::lsm9ds1_s::{AccelerometerEventConfig, AxisEvents, EventRM, GyroEventConfig, GyroTemperatureCalibrationSession, ImuAccelerationData, AccelerometerCalibration, AccelerometerCalibrationSession, CalibrationInfo, FifoConfig, FifoMode, FifoRM, FifoSample, Int1Config, Int2Config, InterruptRM, MagInterruptConfig, AccelerometerConfig, AccelerometerPosition, AccelerometerRM, GyroBandwidth, GyroCalibration, GyroConfig, GyroFullScale, GyroOdr, GyroRM, MagFullScale, MagMode, MagOdr, MagPerformance, MagnetometerCalibration, MagnetometerCalibrationSession, MagnetometerConfig, MagnetometerRM, XlAntiAliasing, GAUSS_TO_MICROTESLA, XlBandwidth, XlFullScale, XlOdr, LSM9DS1};
+
+const AG_ADDR: u8 = 0x6B;
+const M_ADDR: u8 = 0x1E;
+const CS_AG: u8 = 0;
+const CS_M: u8 = 1;
+
+fn imu_bus() -> MockI2c {
+    let mut i2c = MockI2c::new();
+    i2c.set_register(M_ADDR, MagnetometerRM::WhoAmI as u8, 0x3D);
+    i2c.set_auto_increment_bit(M_ADDR);
+    i2c.set_register(M_ADDR, MagnetometerRM::CtrlReg3M as u8, 0x03); //Power-down after reset
+    i2c.set_register(AG_ADDR, GyroRM::StatusReg as u8, 0x03); //New accelerometer and gyro data available
+    i2c
+}
+
+fn imu_spi() -> MockSpi {
+    let mut spi = MockSpi::new();
+    spi.devices.set_register(CS_M, MagnetometerRM::WhoAmI as u8, 0x3D);
+    spi.devices.set_auto_increment_bit(CS_M);
+    spi.devices.set_register(CS_M, MagnetometerRM::CtrlReg3M as u8, 0x03);
+    spi.devices.set_register(CS_AG, GyroRM::StatusReg as u8, 0x03);
+    spi
+}
+
+fn assert_close(actual: f32, expected: f32) {
+    assert!((actual - expected).abs() < 0.01, "{} != {}", actual, expected);
+}
+
+#[test]
+fn sanity_check_accepts_who_am_i() {
+    let mut i2c = imu_bus();
+    let mut imu = LSM9DS1::new(&mut i2c);
+    assert!(imu.sanity_check().is_ok());
+}
+
+#[test]
+fn sanity_check_rejects_wrong_who_am_i() {
+    let mut i2c = imu_bus();
+    i2c.set_register(M_ADDR, MagnetometerRM::WhoAmI as u8, 0x68);
+    let mut imu = LSM9DS1::new(&mut i2c);
+    assert_eq!(imu.sanity_check(), Err(SensorError::WrongId { expected: 0x3D, found: 0x68 }));
+    assert!(matches!(imu.sensor_state(), SensorState::ERROR(SensorError::WrongId { .. })));
+}
+
+#[test]
+fn boot_sequence_writes_control_registers() {
+    let mut i2c = imu_bus();
+    let mut imu = LSM9DS1::new(&mut i2c);
+    assert!(imu.boot_magnetometer().is_ok());
+    assert!(imu.boot_accelerometer().is_ok());
+    assert!(imu.boot_gyroscope().is_ok());
+    drop(imu);
+
+    assert_eq!(i2c.register_writes(M_ADDR), [
+        (MagnetometerRM::CtrlReg1M as u8, vec![0x10]),
+        (MagnetometerRM::CtrlReg2M as u8, vec![0x00]),
+        (MagnetometerRM::CtrlReg4M as u8, vec![0x00]),
+        (MagnetometerRM::CtrlReg5M as u8, vec![0x00]),
+        (MagnetometerRM::CtrlReg3M as u8, vec![0x00])
+    ]);
+    assert_eq!(i2c.register_writes(AG_ADDR), [
+        (AccelerometerRM::CtrlReg6Xl as u8, vec![0xC0]),
+        (AccelerometerRM::CtrlReg7Xl as u8, vec![0x00]),
+        (GyroRM::CtrlReg1G as u8, vec![0xDB]),
+        (GyroRM::CtrlReg2G as u8, vec![0x02]),
+        (GyroRM::CtrlReg3G as u8, vec![0x00])
+    ]);
+}
+
+#[test]
+fn boot_fails_if_register_does_not_change() {
+    let mut i2c = imu_bus();
+    i2c.set_read_only(M_ADDR, MagnetometerRM::CtrlReg3M as u8);
+    let mut imu = LSM9DS1::new(&mut i2c);
+    assert_eq!(imu.boot_magnetometer(), Err(SensorError::InvalidConfiguration));
+}
+
+#[test]
+fn init_boots_all_sensors() {
+    let mut i2c = imu_bus();
+    let mut imu = LSM9DS1::new(&mut i2c);
+    assert!(imu.init().is_ok());
+    assert!(matches!(imu.sensor_state(), SensorState::READY));
+}
+
+#[test]
+fn missing_sensor_is_a_bus_error() {
+    let mut i2c = imu_bus();
+    i2c.nack = true;
+    let mut imu = LSM9DS1::new(&mut i2c);
+    assert_eq!(imu.init(), Err(SensorError::Bus(MockError::Nack)));
+    assert_eq!(imu.sensor_state(), &SensorState::ERROR(SensorError::Bus(())));
+    assert_eq!(imu.read_acceleration(), Err(SensorError::Bus(MockError::Nack)));
+    assert_eq!(imu.sensor_state(), &SensorState::ERROR(SensorError::Bus(())));
+}
+
+#[test]
+fn failed_boot_sets_error_state() {
+    let mut i2c = imu_bus();
+    i2c.set_read_only(M_ADDR, MagnetometerRM::CtrlReg3M as u8);
+    let mut imu = LSM9DS1::new(&mut i2c);
+    assert_eq!(imu.init(), Err(SensorError::InvalidConfiguration));
+    assert_eq!(imu.sensor_state(), &SensorState::ERROR(SensorError::InvalidConfiguration));
+}
+
+#[test]
+fn twos_complement() {
+    let mut i2c = imu_bus();
+    let imu = LSM9DS1::new(&mut i2c);
+    assert_eq!(imu.twos_complement(0x00, 0x01), 1);
+    assert_eq!(imu.twos_complement(0x7F, 0xFF), i16::MAX);
+    assert_eq!(imu.twos_complement(0xFF, 0xFF), -1);
+    assert_eq!(imu.twos_complement(0x80, 0x00), i16::MIN);
+}
+
+#[test]
+fn acceleration_is_scaled_to_g() {
+    let mut i2c = imu_bus();
+    //X = 16384, Y = -16384, Z = 0, little-endian
+    i2c.set_registers(AG_ADDR, AccelerometerRM::OutXXlL as u8, &[0x00, 0x40, 0x00, 0xC0, 0x00, 0x00]);
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    let (x, y, z) = imu.read_acceleration().unwrap();
+    assert_close(x, 1.0);
+    assert_close(y, -1.0);
+    assert_close(z, 0.0);
+}
+
+#[test]
+fn accelerometer_config_sets_registers_and_scale() {
+    let mut i2c = imu_bus();
+    i2c.set_registers(AG_ADDR, AccelerometerRM::OutXXlL as u8, &[0x00, 0x40, 0x00, 0x00, 0x00, 0x00]);
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    let config = AccelerometerConfig {
+        odr: XlOdr::Hz952,
+        full_scale: XlFullScale::G8,
+        anti_aliasing: XlAntiAliasing::Hz105,
+        bandwidth: XlBandwidth::OdrDiv9
+    };
+    assert!(imu.configure_accelerometer(config).is_ok());
+    assert_eq!(imu.accelerometer_config, config);
+
+    //16384 LSB at 0.244 mg/LSB
+    let (x, _, _) = imu.read_acceleration().unwrap();
+    assert_close(x, 3.998);
+    drop(imu);
+
+    assert_eq!(i2c.register(AG_ADDR, AccelerometerRM::CtrlReg6Xl as u8), 0b1101_1110);
+    assert_eq!(i2c.register(AG_ADDR, AccelerometerRM::CtrlReg7Xl as u8), 0b1100_0000);
+}
+
+#[test]
+fn gyro_is_scaled_to_dps() {
+    let mut i2c = imu_bus();
+    i2c.set_registers(AG_ADDR, GyroRM::OutXGL as u8, &[0xFF, 0x7F, 0x01, 0x80, 0x00, 0x00]);
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    //Full scale at 70 mdps/LSB
+    let (x, y, z) = imu.read_gyro().unwrap();
+    assert_close(x, 2293.69);
+    assert_close(y, -2293.69);
+    assert_close(z, 0.0);
+}
+
+#[test]
+fn gyro_config_sets_registers_and_scale() {
+    let mut i2c = imu_bus();
+    i2c.set_registers(AG_ADDR, GyroRM::OutXGL as u8, &[0x00, 0x40, 0x00, 0x00, 0x00, 0x00]);
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    let config = GyroConfig {
+        odr: GyroOdr::Hz238,
+        full_scale: GyroFullScale::Dps500,
+        bandwidth: GyroBandwidth::Narrow,
+        high_pass_cutoff: Some(4)
+    };
+    assert!(imu.configure_gyroscope(config).is_ok());
+
+    //16384 LSB at 17.5 mdps/LSB
+    let (x, _, _) = imu.read_gyro().unwrap();
+    assert_close(x, 286.72);
+    drop(imu);
+
+    assert_eq!(i2c.register(AG_ADDR, GyroRM::CtrlReg1G as u8), 0b1000_1001);
+    assert_eq!(i2c.register(AG_ADDR, GyroRM::CtrlReg3G as u8), 0b0100_0100);
+}
+
+#[test]
+fn gyro_config_rejects_invalid_high_pass_cutoff() {
+    let mut i2c = imu_bus();
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    let config = GyroConfig {
+        high_pass_cutoff: Some(10),
+        ..GyroConfig::default()
+    };
+    assert_eq!(imu.configure_gyroscope(config), Err(SensorError::InvalidConfiguration));
+}
+
+#[test]
+fn magnetometer_axes() {
+    let mut i2c = imu_bus();
+    i2c.set_registers(M_ADDR, MagnetometerRM::OutXLM as u8, &[0x00, 0x10, 0x00, 0xF0, 0x00, 0x08]);
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    //4096 LSB at 0.14 mgauss/LSB
+    assert_close(imu.read_magnetometer_x().unwrap(), 0.5734);
+    assert_close(imu.read_magnetometer_y().unwrap(), -0.5734);
+    assert_close(imu.read_magnetometer_z().unwrap(), 0.2867);
+}
+
+#[test]
+fn magnetometer_reads_all_axes_in_one_burst() {
+    let mut i2c = imu_bus();
+    i2c.set_registers(M_ADDR, MagnetometerRM::OutXLM as u8, &[0x00, 0x10, 0x00, 0xF0, 0x00, 0x08]);
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    //The mock only auto-increments when the sub-address MSB is set, so a plain burst would read OUT_X_L_M six times
+    let (x, y, z) = imu.read_magnetometer().unwrap();
+    assert_close(x, 0.5734);
+    assert_close(y, -0.5734);
+    assert_close(z, 0.2867);
+}
+
+#[test]
+fn magnetometer_applies_hard_iron_offsets() {
+    let mut i2c = imu_bus();
+    i2c.set_registers(M_ADDR, MagnetometerRM::OutXLM as u8, &[0x00, 0x10, 0x00, 0xF0, 0x00, 0x08]);
+    let mut imu = LSM9DS1::new(&mut i2c);
+    imu.calibration_info.magnetometer.x_offset = 4096;
+    imu.calibration_info.magnetometer.y_offset = -2048;
+    imu.calibration_info.magnetometer.z_offset = 1024;
+
+    let (x, y, z) = imu.read_magnetometer().unwrap();
+    assert_close(x, 0.0);
+    assert_close(y, -0.2867);
+    assert_close(z, 0.1434);
+}
+
+#[test]
+fn magnetometer_config_sets_registers_and_scale() {
+    let mut i2c = imu_bus();
+    i2c.set_registers(M_ADDR, MagnetometerRM::OutXLM as u8, &[0x00, 0x10, 0x00, 0x00, 0x00, 0x00]);
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    let config = MagnetometerConfig {
+        odr: MagOdr::Hz80,
+        full_scale: MagFullScale::Gauss16,
+        xy_performance: MagPerformance::UltraHigh,
+        z_performance: MagPerformance::High,
+        temperature_compensation: true,
+        mode: MagMode::Continuous
+    };
+    assert!(imu.configure_magnetometer(config).is_ok());
+
+    //4096 LSB at 0.58 mgauss/LSB
+    assert_close(imu.read_magnetometer_x().unwrap(), 2.3757);
+    assert_close(imu.read_magnetometer_x().unwrap() * GAUSS_TO_MICROTESLA, 237.57);
+    drop(imu);
+
+    assert_eq!(i2c.register(M_ADDR, MagnetometerRM::CtrlReg1M as u8), 0b1111_1100);
+    assert_eq!(i2c.register(M_ADDR, MagnetometerRM::CtrlReg2M as u8), 0b0110_0000);
+    assert_eq!(i2c.register(M_ADDR, MagnetometerRM::CtrlReg4M as u8), 0b0000_1000);
+}
+
+//Readings on an ellipsoid centred on (300, -500, 800) LSB with radii (2000, 2500, 3000) LSB, rotated around Z by rotation (°)
+fn ellipsoid_session(rotation: f32) -> MagnetometerCalibrationSession {
+    let (sin, cos) = (rotation.to_radians().sin(), rotation.to_radians().cos());
+    let mut session = MagnetometerCalibrationSession::new();
+    for latitude in (-90..=90).step_by(30) {
+        for longitude in (0..360).step_by(30) {
+            let (latitude, longitude) = ((latitude as f32).to_radians(), (longitude as f32).to_radians());
+            let (u, v) = (2000.0 * latitude.cos() * longitude.cos(), 2500.0 * latitude.cos() * longitude.sin());
+            let x = 300.0 + cos * u - sin * v;
+            let y = -500.0 + sin * u + cos * v;
+            let z = 800.0 + 3000.0 * latitude.sin();
+            session.add_sample((x.round() as i16, y.round() as i16, z.round() as i16));
+        }
+    }
+    session
+}
+
+#[test]
+fn magnetometer_calibration_fits_ellipsoid() {
+    let result = ellipsoid_session(0.0).finish().unwrap();
+
+    assert_eq!((result.calibration.x_offset, result.calibration.y_offset, result.calibration.z_offset), (300, -500, 800));
+    assert_close(result.calibration.soft_iron[0][0], 1.25);
+    assert_close(result.calibration.soft_iron[1][1], 1.0);
+    assert_close(result.calibration.soft_iron[2][2], 0.8333);
+    assert_close(result.calibration.soft_iron[0][1], 0.0);
+    assert!(result.fit_error < 0.001, "{}", result.fit_error);
+}
+
+#[test]
+fn magnetometer_calibration_fits_rotated_ellipsoid() {
+    let result = ellipsoid_session(30.0).finish().unwrap();
+
+    //The axis scales (1.25, 1.0) rotated by 30° around Z
+    assert_eq!((result.calibration.x_offset, result.calibration.y_offset, result.calibration.z_offset), (300, -500, 800));
+    assert_close(result.calibration.soft_iron[0][0], 1.1875);
+    assert_close(result.calibration.soft_iron[1][1], 1.0625);
+    assert_close(result.calibration.soft_iron[0][1], 0.1083);
+    assert_close(result.calibration.soft_iron[1][0], 0.1083);
+    assert_close(result.calibration.soft_iron[2][2], 0.8333);
+    assert_close(result.calibration.soft_iron[0][2], 0.0);
+    assert!(result.fit_error < 0.001, "{}", result.fit_error);
+}
+
+#[test]
+fn magnetometer_calibration_reports_poor_fit() {
+    //A disturbed field, alternating between 60% and 140% of its magnitude
+    let mut session = MagnetometerCalibrationSession::new();
+    for latitude in (-90..=90).step_by(30) {
+        for longitude in (0..360).step_by(30) {
+            let radius = if (latitude + longitude) % 60 == 0 { 600.0 } else { 1400.0 };
+            let (latitude, longitude) = ((latitude as f32).to_radians(), (longitude as f32).to_radians());
+            let x = radius * latitude.cos() * longitude.cos();
+            let y = radius * latitude.cos() * longitude.sin();
+            let z = radius * latitude.sin();
+            session.add_sample((x.round() as i16, y.round() as i16, z.round() as i16));
+        }
+    }
+
+    let result = session.finish().unwrap();
+    assert!(result.fit_error > 0.1, "{}", result.fit_error);
+}
+
+#[test]
+fn magnetometer_calibration_rejects_non_ellipsoid() {
+    //Corners and face centres of a cube do not lie on an ellipsoid
+    let mut session = MagnetometerCalibrationSession::new();
+    for _ in 0..4 {
+        for x in [-1000i16, 0, 1000] {
+            for y in [-1000i16, 0, 1000] {
+                for z in [-1000i16, 1000] {
+                    session.add_sample((x, y, z));
+                }
+            }
+        }
+    }
+
+    assert_eq!(session.finish(), Err(SensorError::FailedToCalibrate));
+}
+
+#[test]
+fn magnetometer_calibration_needs_enough_rotation() {
+    let mut session = MagnetometerCalibrationSession::new();
+    session.add_sample((100, 200, 300));
+    assert_eq!(session.finish(), Err(SensorError::FailedToCalibrate));
+
+    //Rotated around Z only, so the Z axis never changes
+    for angle in (0..360).step_by(10) {
+        let angle = (angle as f32).to_radians();
+        session.add_sample(((1000.0 * angle.cos()) as i16, (1000.0 * angle.sin()) as i16, 300));
+    }
+    assert_eq!(session.finish(), Err(SensorError::FailedToCalibrate));
+}
+
+#[test]
+fn magnetometer_calibration_samples_sensor() {
+    let mut i2c = imu_bus();
+    i2c.set_registers(M_ADDR, MagnetometerRM::OutXLM as u8, &[0x00, 0x10, 0x00, 0xF0, 0x00, 0x08]);
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    let mut session = MagnetometerCalibrationSession::new();
+    assert!(imu.sample_magnetometer_calibration(&mut session).is_ok());
+    assert_eq!(session.samples, 1);
+    assert_eq!((session.min, session.max), ([4096, -4096, 2048], [4096, -4096, 2048]));
+}
+
+#[test]
+fn magnetometer_calibration_applies_in_driver() {
+    let mut i2c = imu_bus();
+    i2c.set_registers(M_ADDR, MagnetometerRM::OutXLM as u8, &[0x4C, 0x09, 0x0C, 0xFE, 0x20, 0x03]); //(2380, -500, 800)
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    let result = imu.calibrate_magnetometer(&ellipsoid_session(0.0), false).unwrap();
+    assert_eq!(imu.calibration_info.magnetometer, result.calibration);
+
+    //2080 LSB from the centre at 0.14 mgauss/LSB, scaled by 1.25
+    let (x, y, z) = imu.read_magnetometer().unwrap();
+    assert_close(x, 0.364);
+    assert_close(y, 0.0);
+    assert_close(z, 0.0);
+}
+
+#[test]
+fn magnetometer_calibration_writes_offset_registers() {
+    let mut i2c = imu_bus();
+    i2c.set_registers(M_ADDR, MagnetometerRM::OffsetXRegLM as u8, &[0x0A, 0x00, 0x00, 0x00, 0x00, 0x00]); //Existing X offset of 10
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    assert!(imu.calibrate_magnetometer(&ellipsoid_session(0.0), true).is_ok());
+    let calibration = imu.calibration_info.magnetometer;
+    assert_eq!((calibration.x_offset, calibration.y_offset, calibration.z_offset), (0, 0, 0));
+    assert_close(calibration.soft_iron[0][0], 1.25);
+    drop(imu);
+
+    assert_eq!(i2c.register_writes(M_ADDR), [(MagnetometerRM::OffsetXRegLM as u8 | 0x80, vec![0x36, 0x01, 0x0C, 0xFE, 0x20, 0x03])]);
+    assert_eq!(i2c.register(M_ADDR, MagnetometerRM::OffsetZRegHM as u8), 0x03);
+}
+
+#[test]
+fn failed_magnetometer_calibration_sets_error_state() {
+    let mut i2c = imu_bus();
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    assert_eq!(imu.calibrate_magnetometer(&MagnetometerCalibrationSession::new(), false), Err(SensorError::FailedToCalibrate));
+    assert_eq!(imu.state, SensorState::ERROR(SensorError::FailedToCalibrate));
+    assert_eq!(imu.calibration_info.magnetometer, MagnetometerCalibration::default());
+}
+
+#[test]
+fn gyro_calibration_averages_samples() {
+    let mut i2c = imu_bus();
+    for x in [0x60, 0x70, 0x80, 0x90] {
+        i2c.script_read(AG_ADDR, GyroRM::OutXGL as u8, &[x, 0x00, 0xE0, 0xFF, 0x05, 0x00]); //(x, -32, 5)
+    }
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    //Mean X of 120 LSB at 70 mdps/LSB
+    let calibration = imu.calibrate_gyroscope(4, 2.0).unwrap();
+    assert_close(calibration.x_bias, 8.4);
+    assert_close(calibration.y_bias, -2.24);
+    assert_close(calibration.z_bias, 0.35);
+    assert_eq!(imu.calibration_info.gyroscope, calibration);
+
+    //Readings at the mean are zero once the bias is removed
+    imu.interface.i2c.set_registers(AG_ADDR, GyroRM::OutXGL as u8, &[0x78, 0x00, 0xE0, 0xFF, 0x05, 0x00]);
+    let (x, y, z) = imu.read_gyro().unwrap();
+    assert_close(x, 0.0);
+    assert_close(y, 0.0);
+    assert_close(z, 0.0);
+}
+
+#[test]
+fn gyro_calibration_rejects_movement() {
+    let mut i2c = imu_bus();
+    for x in [0x00, 0x40, 0x00, 0x40] {
+        i2c.script_read(AG_ADDR, GyroRM::OutXGL as u8, &[0x00, x, 0x00, 0x00, 0x00, 0x00]); //X alternates between 0 and 1147 dps
+    }
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    assert_eq!(imu.calibrate_gyroscope(4, 2.0), Err(SensorError::FailedToCalibrate));
+    assert_eq!(imu.state, SensorState::ERROR(SensorError::FailedToCalibrate));
+    assert_eq!(imu.calibration_info.gyroscope, GyroCalibration::default());
+}
+
+#[test]
+fn init_keeps_restored_gyro_bias() {
+    let mut i2c = imu_bus();
+    let mut imu = LSM9DS1::new(&mut i2c);
+    imu.calibration_info.gyroscope.x_bias = 1.5;
+
+    assert!(imu.init().is_ok());
+    assert_eq!(imu.calibration_info.gyroscope.x_bias, 1.5);
+}
+
+#[test]
+fn sensor_calibrate_keeps_bias_when_moving() {
+    let mut i2c = imu_bus();
+    i2c.script_read(AG_ADDR, GyroRM::OutXGL as u8, &[0x00, 0x40, 0x00, 0x00, 0x00, 0x00]); //One sample at 1147 dps, the rest at 0
+    let mut imu = LSM9DS1::new(&mut i2c);
+    imu.calibration_info.gyroscope.x_bias = 1.5;
+
+    assert_eq!(imu.calibrate(), Err(SensorError::FailedToCalibrate));
+    assert_eq!(imu.sensor_state(), &SensorState::READY);
+    assert_eq!(imu.calibration_info.gyroscope.x_bias, 1.5);
+}
+
+#[test]
+fn gyro_calibration_times_out_without_data() {
+    let mut i2c = imu_bus();
+    i2c.set_register(AG_ADDR, GyroRM::StatusReg as u8, 0x00);
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    assert_eq!(imu.calibrate_gyroscope(4, 2.0), Err(SensorError::Timeout));
+}
+
+fn acceleration_bytes(x: i16, y: i16, z: i16) -> [u8; 6] {
+    let (x, y, z) = (x.to_le_bytes(), y.to_le_bytes(), z.to_le_bytes());
+    [x[0], x[1], y[0], y[1], z[0], z[1]]
+}
+
+#[test]
+fn six_position_calibration_computes_offset_and_scale() {
+    let mut i2c = imu_bus();
+    //X reads 1.07 g up and -0.97 g down, i.e. an offset of 0.05 g and a gain of 1.02
+    let positions = [
+        (AccelerometerPosition::XUp, acceleration_bytes(17541, 0, 0)),
+        (AccelerometerPosition::XDown, acceleration_bytes(-15902, 0, 0)),
+        (AccelerometerPosition::YUp, acceleration_bytes(0, 16393, 0)),
+        (AccelerometerPosition::YDown, acceleration_bytes(0, -16393, 0)),
+        (AccelerometerPosition::ZUp, acceleration_bytes(0, 0, 16393)),
+        (AccelerometerPosition::ZDown, acceleration_bytes(0, 0, -16393))
+    ];
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    let mut session = AccelerometerCalibrationSession::new();
+    for (position, bytes) in positions {
+        for _ in 0..2 {
+            imu.interface.i2c.script_read(AG_ADDR, AccelerometerRM::OutXXlL as u8, &bytes);
+        }
+        assert!(imu.sample_accelerometer_position(&mut session, position, 2).is_ok());
+    }
+
+    let calibration = imu.calibrate_accelerometer(&session).unwrap();
+    assert_close(calibration.x_offset, 0.05);
+    assert_close(calibration.x_scale, 0.9804);
+    assert_close(calibration.y_offset, 0.0);
+    assert_close(calibration.z_scale, 1.0);
+    assert_eq!(imu.calibration_info.accelerometer, calibration);
+
+    imu.interface.i2c.set_registers(AG_ADDR, AccelerometerRM::OutXXlL as u8, &acceleration_bytes(17541, 0, -16393));
+    let (x, y, z) = imu.read_acceleration().unwrap();
+    assert_close(x, 1.0);
+    assert_close(y, 0.0);
+    assert_close(z, -1.0);
+}
+
+#[test]
+fn six_position_calibration_rejects_wrong_orientation() {
+    let mut i2c = imu_bus();
+    i2c.set_registers(AG_ADDR, AccelerometerRM::OutXXlL as u8, &acceleration_bytes(0, 0, 16393));
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    let mut session = AccelerometerCalibrationSession::new();
+    assert_eq!(imu.sample_accelerometer_position(&mut session, AccelerometerPosition::XUp, 4), Err(SensorError::FailedToCalibrate));
+    assert_eq!(imu.sample_accelerometer_position(&mut session, AccelerometerPosition::ZDown, 4), Err(SensorError::FailedToCalibrate));
+    assert_close(imu.sample_accelerometer_position(&mut session, AccelerometerPosition::ZUp, 4).unwrap(), 1.0);
+    assert_eq!(session.readings[AccelerometerPosition::XUp as usize], None);
+}
+
+#[test]
+fn six_position_calibration_needs_every_position() {
+    let mut i2c = imu_bus();
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    let mut session = AccelerometerCalibrationSession::new();
+    session.readings = [Some(1.0), Some(-1.0), Some(1.0), Some(-1.0), Some(1.0), None];
+    assert_eq!(imu.calibrate_accelerometer(&session), Err(SensorError::FailedToCalibrate));
+    assert_eq!(imu.state, SensorState::ERROR(SensorError::FailedToCalibrate));
+    assert_eq!(imu.calibration_info.accelerometer, AccelerometerCalibration::default());
+}
+
+#[test]
+fn level_calibration_zeroes_tilt() {
+    let mut i2c = imu_bus();
+    i2c.set_registers(AG_ADDR, AccelerometerRM::OutXXlL as u8, &acceleration_bytes(820, -410, 17213)); //(0.05, -0.025, 1.05) g
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    let calibration = imu.level_accelerometer(4).unwrap();
+    assert_close(calibration.x_offset, 0.05);
+    assert_close(calibration.y_offset, -0.025);
+    assert_close(calibration.z_offset, 0.05);
+    assert_close(calibration.z_scale, 1.0);
+
+    let (x, y, z) = imu.read_acceleration().unwrap();
+    assert_close(x, 0.0);
+    assert_close(y, 0.0);
+    assert_close(z, 1.0);
+}
+
+#[test]
+fn calibration_is_restored_from_record() {
+    let mut i2c = imu_bus();
+    let mut imu = LSM9DS1::new(&mut i2c);
+    imu.calibration_info.magnetometer.x_offset = -120;
+    imu.calibration_info.magnetometer.soft_iron[1][2] = 0.05;
+    imu.calibration_info.gyroscope.z_bias = 0.7;
+    imu.calibration_info.gyroscope.y_slope = -0.02;
+    imu.calibration_info.accelerometer.y_scale = 0.98;
+
+    let mut record = [0u8; 128];
+    let len = imu.store_calibration(&mut record).unwrap();
+    assert_eq!(len, 108);
+    let saved = imu.calibration_info;
+    drop(imu);
+
+    let mut i2c = imu_bus();
+    let mut imu = LSM9DS1::new(&mut i2c);
+    assert!(imu.load_calibration(&record[..len]).is_ok());
+    assert_eq!(imu.calibration_info, saved);
+
+    //A corrupted record leaves the current calibration in place
+    record[10] ^= 0x01;
+    imu.calibration_info = CalibrationInfo::default();
+    assert_eq!(imu.load_calibration(&record[..len]), Err(CalibrationError::Corrupted));
+    assert_eq!(imu.calibration_info, CalibrationInfo::default());
+}
+
+#[test]
+fn fifo_config_sets_registers() {
+    let mut i2c = imu_bus();
+    i2c.set_register(AG_ADDR, FifoRM::CtrlReg9 as u8, 0b0100_0000); //SLEEP_G is kept
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    assert!(imu.configure_fifo(FifoConfig { mode: FifoMode::Continuous, threshold: 20 }).is_ok());
+    assert_eq!(imu.interface.i2c.register(AG_ADDR, FifoRM::CtrlReg9 as u8), 0b0100_0010);
+    assert_eq!(imu.interface.i2c.register(AG_ADDR, FifoRM::FifoCtrl as u8), 0b1101_0100);
+
+    assert!(imu.configure_fifo(FifoConfig::default()).is_ok());
+    assert_eq!(imu.interface.i2c.register(AG_ADDR, FifoRM::CtrlReg9 as u8), 0b0100_0000);
+    assert_eq!(imu.interface.i2c.register(AG_ADDR, FifoRM::FifoCtrl as u8), 0x00);
+
+    assert_eq!(imu.configure_fifo(FifoConfig { mode: FifoMode::Fifo, threshold: 32 }), Err(SensorError::InvalidConfiguration));
+}
+
+#[test]
+fn fifo_status_is_decoded() {
+    let mut i2c = imu_bus();
+    i2c.set_register(AG_ADDR, FifoRM::FifoSrc as u8, 0b1110_0000); //Threshold reached, overrun, 32 samples
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    let status = imu.read_fifo_status().unwrap();
+    assert_eq!((status.samples, status.threshold_reached, status.overrun), (32, true, true));
+}
+
+#[test]
+fn fifo_samples_are_read_oldest_first_with_timestamps() {
+    let mut i2c = imu_bus();
+    i2c.set_register(AG_ADDR, FifoRM::FifoSrc as u8, 3);
+    for i in 1..=3 {
+        i2c.script_read(AG_ADDR, GyroRM::OutXGL as u8, &acceleration_bytes(100 * i, 0, 0));
+        i2c.script_read(AG_ADDR, AccelerometerRM::OutXXlL as u8, &acceleration_bytes(0, 0, 8197 * i));
+    }
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    //The buffer only has room for two of the three samples
+    let mut samples = [FifoSample::default(); 2];
+    assert_eq!(imu.read_fifo(&mut samples, 10_000), Ok(2));
+
+    //952 Hz, so the samples are 1050 µs apart and the newest sample (still in the FIFO) is at 10000 µs
+    assert_eq!(samples[0].timestamp_us, 7_900);
+    assert_eq!(samples[1].timestamp_us, 8_950);
+    assert_close(samples[0].gyro.0, 7.0);
+    assert_close(samples[1].gyro.0, 14.0);
+    assert_close(samples[0].acceleration.2, 0.5);
+    assert_close(samples[1].acceleration.2, 1.0);
+}
+
+#[test]
+fn empty_fifo_reads_nothing() {
+    let mut i2c = imu_bus();
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    let mut samples = [FifoSample::default(); 4];
+    assert_eq!(imu.read_fifo(&mut samples, 0), Ok(0));
+}
+
+#[test]
+fn interrupt_pins_are_configured() {
+    let mut i2c = imu_bus();
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    let int1 = Int1Config {
+        gyro_data_ready: true,
+        fifo_threshold: true,
+        accelerometer_event: true,
+        ..Int1Config::default()
+    };
+    let int2 = Int2Config {
+        accelerometer_data_ready: true,
+        temperature_data_ready: true,
+        inactivity: true,
+        ..Int2Config::default()
+    };
+    assert!(imu.configure_int1(int1).is_ok());
+    assert!(imu.configure_int2(int2).is_ok());
+    drop(imu);
+
+    assert_eq!(i2c.register_writes(AG_ADDR), [
+        (InterruptRM::Int1Ctrl as u8, vec![0b0100_1010]),
+        (InterruptRM::Int2Ctrl as u8, vec![0b1000_0101])
+    ]);
+}
+
+#[test]
+fn magnetometer_interrupt_is_configured() {
+    let mut i2c = imu_bus();
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    let config = MagInterruptConfig {
+        x: true,
+        z: true,
+        threshold: 0x1234,
+        active_high: true,
+        latched: true,
+        ..MagInterruptConfig::default()
+    };
+    assert!(imu.configure_magnetometer_interrupt(config).is_ok());
+    assert_eq!(imu.configure_magnetometer_interrupt(MagInterruptConfig { threshold: 0x8000, ..config }), Err(SensorError::InvalidConfiguration));
+    drop(imu);
+
+    assert_eq!(i2c.register_writes(M_ADDR), [
+        (MagnetometerRM::IntThsLM as u8, vec![0x34]),
+        (MagnetometerRM::IntThsHM as u8, vec![0x12]),
+        (MagnetometerRM::IntCfgM as u8, vec![0b1010_0101]) //Latched, so IEL is clear
+    ]);
+}
+
+#[test]
+fn status_registers_are_decoded() {
+    let mut i2c = imu_bus();
+    i2c.set_register(AG_ADDR, GyroRM::StatusReg as u8, 0b0100_0110);
+    i2c.set_register(M_ADDR, MagnetometerRM::StatusRegM as u8, 0b1000_1111);
+    i2c.set_register(M_ADDR, MagnetometerRM::IntSrcM as u8, 0b1000_1001);
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    let status = imu.read_status().unwrap();
+    assert!(status.gyro_data && status.temperature_data && status.accelerometer_event);
+    assert!(!status.accelerometer_data && !status.gyro_event && !status.inactivity);
+    assert_eq!(imu.accelerometer_data_ready(), Ok(false));
+    assert_eq!(imu.gyro_data_ready(), Ok(true));
+
+    let status = imu.read_magnetometer_status().unwrap();
+    assert!(status.data && status.overrun);
+    assert_eq!(imu.magnetometer_data_ready(), Ok(true));
+
+    let source = imu.read_magnetometer_interrupt_source().unwrap();
+    assert_eq!((source.positive, source.negative, source.active), ((true, false, false), (false, true, false), true));
+}
+
+#[test]
+fn free_fall_event_is_configured() {
+    let mut i2c = imu_bus();
+    i2c.set_register(AG_ADDR, EventRM::CtrlReg4 as u8, 0x38); //Gyro axes enabled after reset
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    //±2 g, so a threshold step is 7.8 mg
+    assert!(imu.configure_accelerometer_events(AccelerometerEventConfig::free_fall(0.35, 3)).is_ok());
+    assert_eq!(imu.interface.i2c.register(AG_ADDR, EventRM::CtrlReg4 as u8), 0x3A);
+    assert_eq!(imu.interface.i2c.register(AG_ADDR, EventRM::IntGenThsXXl as u8), 45);
+    assert_eq!(imu.interface.i2c.register(AG_ADDR, EventRM::IntGenThsYXl as u8), 45);
+    assert_eq!(imu.interface.i2c.register(AG_ADDR, EventRM::IntGenThsZXl as u8), 45);
+    assert_eq!(imu.interface.i2c.register(AG_ADDR, EventRM::IntGenDurXl as u8), 3);
+    assert_eq!(imu.interface.i2c.register(AG_ADDR, EventRM::IntGenCfgXl as u8), 0b1001_0101);
+
+    assert!(imu.configure_accelerometer_events(AccelerometerEventConfig::orientation(0.5, 0)).is_ok());
+    assert_eq!(imu.interface.i2c.register(AG_ADDR, EventRM::CtrlReg4 as u8), 0x38);
+    assert_eq!(imu.interface.i2c.register(AG_ADDR, EventRM::IntGenCfgXl as u8), 0b1111_1111);
+}
+
+#[test]
+fn event_thresholds_are_validated() {
+    let mut i2c = imu_bus();
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    assert_eq!(imu.configure_accelerometer_events(AccelerometerEventConfig::wake_up(2.5, 0)), Err(SensorError::InvalidConfiguration));
+    assert_eq!(imu.configure_accelerometer_events(AccelerometerEventConfig::wake_up(-0.1, 0)), Err(SensorError::InvalidConfiguration));
+    assert_eq!(imu.configure_accelerometer_events(AccelerometerEventConfig::wake_up(0.5, 128)), Err(SensorError::InvalidConfiguration));
+    assert_eq!(imu.configure_gyro_events(GyroEventConfig::rotation(2500.0, 0)), Err(SensorError::InvalidConfiguration));
+    assert_eq!(imu.sensor_state(), &SensorState::INITIAL);
+    drop(imu);
+
+    assert!(i2c.writes.is_empty());
+}
+
+#[test]
+fn gyro_event_is_configured() {
+    let mut i2c = imu_bus();
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    let config = GyroEventConfig {
+        threshold: (70.0, 0.0, 245.0),
+        decrement: true,
+        ..GyroEventConfig::rotation(0.0, 10)
+    };
+    assert!(imu.configure_gyro_events(config).is_ok());
+    drop(imu);
+
+    //±2000 dps, so the thresholds are 1000 and 3500 LSB
+    assert_eq!(i2c.register_writes(AG_ADDR), [
+        (EventRM::IntGenThsXHG as u8, vec![0x83, 0xE8, 0x00, 0x00, 0x0D, 0xAC, 10]),
+        (EventRM::IntGenCfgG as u8, vec![0b0110_1010])
+    ]);
+}
+
+#[test]
+fn inertial_events_are_decoded() {
+    let mut i2c = imu_bus();
+    i2c.set_register(AG_ADDR, EventRM::IntGenSrcXl as u8, 0b0101_0101);
+    i2c.set_register(AG_ADDR, EventRM::IntGenSrcG as u8, 0b0000_0010);
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    let events = imu.read_accelerometer_events().unwrap();
+    assert!(events.active);
+    assert_eq!(events.events, AxisEvents::ALL_LOW);
+
+    let events = imu.read_gyro_events().unwrap();
+    assert!(!events.active);
+    assert_eq!(events.events, AxisEvents { x_high: true, ..AxisEvents::default() });
+}
+
+#[test]
+fn temperature_is_read_in_celsius() {
+    let mut i2c = imu_bus();
+    i2c.set_registers(AG_ADDR, GyroRM::OutTempL as u8, &[0xB0, 0xFF]); //-80 LSB
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    assert_close(imu.read_temperature().unwrap(), 20.0);
+}
+
+#[test]
+fn gyro_bias_follows_temperature_model() {
+    let mut i2c = imu_bus();
+    //Bias of 100 LSB (7 dps) on X at 25 °C and 200 LSB (14 dps) at 35 °C
+    for (temperature, x) in [(0x00u8, 0x64u8), (0xA0, 0xC8)] {
+        i2c.script_read(AG_ADDR, GyroRM::OutTempL as u8, &[temperature, 0x00]);
+        i2c.script_read(AG_ADDR, GyroRM::OutTempL as u8, &[temperature, 0x00]);
+        for _ in 0..4 {
+            i2c.script_read(AG_ADDR, GyroRM::OutXGL as u8, &[x, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        }
+    }
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    let mut session = GyroTemperatureCalibrationSession::new();
+    assert!(imu.sample_gyro_temperature_calibration(&mut session, 4, 1.0).is_ok());
+    assert!(imu.sample_gyro_temperature_calibration(&mut session, 4, 1.0).is_ok());
+
+    let calibration = imu.calibrate_gyro_temperature(&session).unwrap();
+    assert_close(calibration.reference_temperature, 30.0);
+    assert_close(calibration.x_bias, 10.5);
+    assert_close(calibration.x_slope, 0.7);
+    assert_close(calibration.y_slope, 0.0);
+
+    //At 40 °C the bias is 17.5 dps
+    imu.interface.i2c.set_registers(AG_ADDR, GyroRM::OutTempL as u8, &[0xF0, 0x00]);
+    imu.interface.i2c.set_registers(AG_ADDR, GyroRM::OutXGL as u8, &[0xFA, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    assert_close(imu.read_gyro().unwrap().0, 0.0);
+
+    //Recalibrating at rest keeps the slopes, and re-references the model to the current temperature
+    assert!(imu.calibrate_gyroscope(1, 1.0).is_ok());
+    let recalibrated = imu.calibration_info.gyroscope;
+    assert_close(recalibrated.x_bias, 17.5);
+    assert_close(recalibrated.x_slope, 0.7);
+    assert_close(recalibrated.reference_temperature, 40.0);
+}
+
+#[test]
+fn gyro_temperature_model_needs_a_temperature_range() {
+    let mut i2c = imu_bus();
+    let mut imu = LSM9DS1::new(&mut i2c);
+
+    let mut session = GyroTemperatureCalibrationSession::new();
+    session.add_point(25.0, (1.0, 0.0, 0.0));
+    session.add_point(27.0, (1.1, 0.0, 0.0));
+    assert_eq!(imu.calibrate_gyro_temperature(&session), Err(SensorError::FailedToCalibrate));
+    assert_eq!(imu.state, SensorState::ERROR(SensorError::FailedToCalibrate));
+    assert_eq!(imu.calibration_info.gyroscope, GyroCalibration::default());
+}
+
+#[test]
+fn read_all_reads_every_axis() {
+    let mut i2c = imu_bus();
+    //OUT_TEMP = 32 LSB, STATUS_REG, then gyro X = 100 LSB
+    i2c.set_registers(AG_ADDR, GyroRM::OutTempL as u8, &[0x20, 0x00, 0x03, 0x64, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    i2c.set_registers(AG_ADDR, AccelerometerRM::OutXXlL as u8, &acceleration_bytes(0, 0, 16393));
+    i2c.set_registers(M_ADDR, MagnetometerRM::OutXLM as u8, &[0xE8, 0x03, 0x00, 0x00, 0x18, 0xFC]); //(1000, 0, -1000)
+    let mut imu = LSM9DS1::new(&mut i2c);
+    assert_eq!(imu.data.sample, None);
+
+    let sample = imu.read_all(12_345).unwrap();
+    assert_eq!(sample.timestamp_us, 12_345);
+    assert_close(sample.temperature, 27.0);
+    assert_close(sample.gyro.0, 7.0);
+    assert_close(sample.acceleration.2, 1.0);
+    assert_close(sample.magnetometer.0, 0.14);
+    assert_close(sample.magnetometer.2, -0.14);
+
+    assert_eq!(imu.data.sample, Some(sample));
+    assert_eq!(imu.data.acceleration, ImuAccelerationData { x: 0.0, y: 0.0, z: sample.acceleration.2 });
+}
+
+#[test]
+fn init_over_spi_enables_magnetometer_reads() {
+    let mut spi = imu_spi();
+    let (cs_ag, cs_m) = (spi.chip_select(CS_AG), spi.chip_select(CS_M));
+    let mut imu = LSM9DS1::new_spi(&mut spi, cs_ag, cs_m);
+
+    assert!(imu.init().is_ok());
+    assert_eq!(imu.sensor_state(), &SensorState::READY);
+    drop(imu);
+
+    //SIM is set before WHO_AM_I is read, and kept set when the magnetometer is started
+    assert_eq!(spi.addresses[..2], [(CS_M, MagnetometerRM::CtrlReg3M as u8), (CS_M, 0x80 | MagnetometerRM::WhoAmI as u8)]);
+    assert_eq!(spi.devices.register_writes(CS_M)[0], (MagnetometerRM::CtrlReg3M as u8, vec![MagMode::PowerDown as u8 | 0b100]));
+    assert_eq!(spi.devices.register(CS_M, MagnetometerRM::CtrlReg3M as u8), 0b100);
+    assert_eq!(spi.devices.register(CS_AG, GyroRM::CtrlReg1G as u8), GyroConfig::default().ctrl_reg1_g());
+}
+
+#[test]
+fn spi_bursts_set_read_and_auto_increment_bits() {
+    let mut spi = imu_spi();
+    spi.devices.set_registers(CS_M, MagnetometerRM::OutXLM as u8, &[0xE8, 0x03, 0x00, 0x00, 0x18, 0xFC]); //(1000, 0, -1000)
+    spi.devices.set_registers(CS_AG, GyroRM::OutXGL as u8, &[0x64, 0x00, 0x00, 0x00, 0x9C, 0xFF]); //(100, 0, -100)
+    let (cs_ag, cs_m) = (spi.chip_select(CS_AG), spi.chip_select(CS_M));
+    let mut imu = LSM9DS1::new_spi(&mut spi, cs_ag, cs_m);
+
+    assert_eq!(imu.read_raw_magnetometer(), Ok((1000, 0, -1000)));
+    assert_eq!(imu.read_raw_gyro(), Ok((100, 0, -100)));
+    assert!(imu.configure_int1(Int1Config::default()).is_ok());
+    drop(imu);
+
+    assert_eq!(spi.addresses, [
+        (CS_M, 0x80 | 0x40 | MagnetometerRM::OutXLM as u8),
+        (CS_AG, 0x80 | GyroRM::OutXGL as u8),
+        (CS_AG, InterruptRM::Int1Ctrl as u8)
+    ]);
+}
+
+#[test]
+fn spi_errors_are_bus_errors() {
+    let mut spi = imu_spi();
+    spi.devices.nack = true;
+    let (cs_ag, cs_m) = (spi.chip_select(CS_AG), spi.chip_select(CS_M));
+    let mut imu = LSM9DS1::new_spi(&mut spi, cs_ag, cs_m);
+
+    assert_eq!(imu.init(), Err(SensorError::Bus(SpiInterfaceError::Spi(MockError::Nack))));
+    assert_eq!(imu.read_temperature(), Err(SensorError::Bus(SpiInterfaceError::Spi(MockError::Nack))));
+    assert_eq!(imu.sensor_state(), &SensorState::ERROR(SensorError::Bus(())));
+
+    //The chip-select was released, so the next transfer starts afresh
+    imu.interface.spi.devices.nack = false;
+    imu.interface.spi.devices.set_registers(CS_AG, GyroRM::OutTempL as u8, &[0x10, 0x00]);
+    assert_eq!(imu.read_temperature(), Ok(26.0));
+}
+
+#[test]
+fn long_i2c_writes_are_split_into_chunks() {
+    let mut i2c = imu_bus();
+    let data: Vec<u8> = (1..=10).collect();
+    let mut interface = I2cInterface::new(&mut i2c);
+
+    assert!(interface.write_registers(Device::AccelGyro, 0x10, &data).is_ok());
+    assert!(interface.write_registers(Device::Magnetometer, 0x05, &data[..9]).is_ok());
+
+    assert_eq!(i2c.register_writes(AG_ADDR), [(0x10, data[..8].to_vec()), (0x18, data[8..].to_vec())]);
+    assert_eq!(i2c.register_writes(M_ADDR), [(0x05 | 0x80, data[..8].to_vec()), (0x0D, data[8..9].to_vec())]);
+    assert_eq!(i2c.register(M_ADDR, 0x0D), 9);
+}
@@ -0,0 +1,21 @@
+/*
+ * Non-volatile storage for calibration and mission parameters.
+ *
+ * The record store works on any flash implementing the embedded-storage NorFlash trait, such as the
+ * stm32f4xx-hal UnlockedFlash, or a RAM-backed flash in host tests.
+ *
+ * On the STM32F411 the store uses sectors 5 and 6, which memory.x keeps out of the program's FLASH region.
+ * The HAL erases 128K at a time, so each sector is one page of the store. This needs a part with 512K of flash
+ * (STM32F411xE, as on the Nucleo and Black Pill boards). On a 256K STM32F411xC sector 6 does not exist, and mount
+ * returns StoreError::OutOfRange.
+ *
+ * let mut flash = LockedFlash::new(dp.FLASH);
+ * let mut unlocked = flash.unlocked();
+ * let mut store = RecordStore::mount(&mut unlocked, STORAGE_OFFSET, STORAGE_PAGES)?;
+ * store.save(&imu.calibration_info)?;
+ */
+pub mod record_store;
+
+//Offset of the reserved sectors from the start of flash (0x08020000)
+pub const STORAGE_OFFSET: u32 = 0x20000;
+pub const STORAGE_PAGES: u32 = 2;
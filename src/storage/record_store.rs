@@ -0,0 +1,496 @@
+/*
+ * Append-only key-value store on NOR flash.
+ *
+ * The storage region is split into pages of NorFlash::ERASE_SIZE. One page is active at a time, and every write
+ * appends an entry to it, so a value only ever costs a write and pages are only erased when they fill up.
+ * The latest entry for a key is its current value, and an entry with no data removes the key.
+ *
+ * When the active page is full, the current value of every key is copied to the next page, which then becomes
+ * active (compaction). Pages are used in turn, spreading erases evenly over the region.
+ * The page magic is written last, so a compaction interrupted by a power loss leaves the previous page active.
+ *
+ * Page: | sequence (u32) | magic (u32) | entries ... |
+ * Entry: | key (u8) | length (u16) | data | CRC-32 of key, length and data (u32) |, padded to the write size
+ */
+use byteorder::{ByteOrder, LittleEndian};
+use embedded_storage::nor_flash::NorFlash;
+use crate::sensor::calibration::{crc32, CalibrationError, Persistent};
+
+const PAGE_MAGIC: u32 = 0x5354_4F52;
+const PAGE_HEADER_LEN: u32 = 8;
+const ENTRY_HEADER_LEN: usize = 3;
+const ENTRY_CRC_LEN: usize = 4;
+const ERASED_KEY: u8 = 0xFF;
+pub const MAX_VALUE_LEN: usize = 244;
+const ENTRY_BUFFER_LEN: usize = 256;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StoreError<E> {
+    Flash(E),
+    InvalidRegion, //The region is not made of at least two whole pages, or the flash has a write or read size above 4 bytes
+    OutOfRange, //The region extends past the end of the flash, as sectors 5 and 6 do on a 256K STM32F411xC
+    InvalidKey, //0xFF marks erased flash and can not be used as a key
+    InvalidLength, //Values must be between 1 and MAX_VALUE_LEN bytes
+    NoSpace, //The current values do not fit in a page
+    BufferTooSmall,
+    Record(CalibrationError)
+}
+
+enum Entry {
+    End,
+    Valid {
+        key: u8,
+        len: usize,
+        next: u32
+    },
+    Corrupted {
+        next: u32
+    }
+}
+
+pub struct RecordStore<'a, F> {
+    flash: &'a mut F,
+    base: u32,
+    pages: u32,
+    active: u32,
+    sequence: u32,
+    write_offset: u32 //Offset of the next entry in the active page
+}
+
+impl<'a, F> RecordStore<'a, F> where F: NorFlash {
+    //Opens the store in the pages starting at base (an offset from the start of the flash), formatting it if it is blank
+    pub fn mount(flash: &'a mut F, base: u32, pages: u32) -> Result<Self, StoreError<F::Error>> {
+        let page_size = F::ERASE_SIZE as u32;
+        if pages < 2 || !base.is_multiple_of(page_size) || 4 % F::WRITE_SIZE != 0 || 4 % F::READ_SIZE != 0 {
+            return Err(StoreError::InvalidRegion);
+        }
+        if base as usize + pages as usize * page_size as usize > flash.capacity() {
+            return Err(StoreError::OutOfRange);
+        }
+
+        let mut store = RecordStore {
+            flash,
+            base,
+            pages,
+            active: 0,
+            sequence: 0,
+            write_offset: PAGE_HEADER_LEN
+        };
+
+        let mut found = false;
+        for page in 0..pages {
+            if let Some(sequence) = store.page_sequence(page)? {
+                if !found || sequence > store.sequence {
+                    store.active = page;
+                    store.sequence = sequence;
+                    found = true;
+                }
+            }
+        }
+
+        if found {
+            store.write_offset = store.end_of_entries()?;
+        } else {
+            store.format_page(0, 1)?;
+            store.write_page_magic(0)?;
+            store.sequence = 1;
+        }
+
+        Ok(store)
+    }
+
+    //Copies the current value of key into buffer, returning its length, or None if the key is not set
+    pub fn read(&mut self, key: u8, buffer: &mut [u8]) -> Result<Option<usize>, StoreError<F::Error>> {
+        let mut entry_buffer = [0u8; ENTRY_BUFFER_LEN];
+        let latest = self.latest_entry(key, PAGE_HEADER_LEN, &mut entry_buffer)?;
+
+        match latest {
+            Some((offset, len)) if len > 0 => {
+                if buffer.len() < len {
+                    return Err(StoreError::BufferTooSmall);
+                }
+                self.next_entry(self.active, offset, &mut entry_buffer)?;
+                buffer[..len].copy_from_slice(&entry_buffer[ENTRY_HEADER_LEN..ENTRY_HEADER_LEN + len]);
+                Ok(Some(len))
+            },
+            _ => Ok(None)
+        }
+    }
+
+    pub fn write(&mut self, key: u8, data: &[u8]) -> Result<(), StoreError<F::Error>> {
+        if data.is_empty() || data.len() > MAX_VALUE_LEN {
+            return Err(StoreError::InvalidLength);
+        }
+        self.append(key, data)
+    }
+
+    pub fn remove(&mut self, key: u8) -> Result<(), StoreError<F::Error>> {
+        let mut entry_buffer = [0u8; ENTRY_BUFFER_LEN];
+        match self.latest_entry(key, PAGE_HEADER_LEN, &mut entry_buffer)? {
+            Some((_, len)) if len > 0 => self.append(key, &[]),
+            _ => Ok(())
+        }
+    }
+
+    //Stores calibration data as a record, using its record kind as the key. Keys used for other values should not clash with the kinds in sensor::calibration
+    pub fn save<T: Persistent>(&mut self, value: &T) -> Result<(), StoreError<F::Error>> {
+        let mut record = [0u8; MAX_VALUE_LEN];
+        let len = value.encode(&mut record).map_err(StoreError::Record)?;
+        self.write(T::KIND, &record[..len])
+    }
+
+    pub fn load<T: Persistent>(&mut self) -> Result<Option<T>, StoreError<F::Error>> {
+        let mut record = [0u8; MAX_VALUE_LEN];
+        match self.read(T::KIND, &mut record)? {
+            Some(len) => T::decode(&record[..len]).map(Some).map_err(StoreError::Record),
+            None => Ok(None)
+        }
+    }
+
+    //Number of free bytes left in the active page
+    pub fn free_space(&self) -> usize {
+        F::ERASE_SIZE - self.write_offset as usize
+    }
+
+    fn append(&mut self, key: u8, data: &[u8]) -> Result<(), StoreError<F::Error>> {
+        if key == ERASED_KEY {
+            return Err(StoreError::InvalidKey);
+        }
+
+        let mut entry_buffer = [0xFFu8; ENTRY_BUFFER_LEN];
+        let entry_len = Self::encode_entry(key, data, &mut entry_buffer);
+
+        if self.write_offset as usize + entry_len > F::ERASE_SIZE {
+            self.compact()?;
+            if self.write_offset as usize + entry_len > F::ERASE_SIZE {
+                return Err(StoreError::NoSpace);
+            }
+        }
+
+        let address = self.page_address(self.active) + self.write_offset;
+        self.flash.write(address, &entry_buffer[..entry_len]).map_err(StoreError::Flash)?;
+        self.write_offset += entry_len as u32;
+        Ok(())
+    }
+
+    //Copies the current value of every key into the next page, and makes it the active page
+    fn compact(&mut self) -> Result<(), StoreError<F::Error>> {
+        let next_page = (self.active + 1) % self.pages;
+        self.format_page(next_page, self.sequence.wrapping_add(1))?;
+
+        let mut entry_buffer = [0u8; ENTRY_BUFFER_LEN];
+        let mut next_write_offset = PAGE_HEADER_LEN;
+        let mut offset = PAGE_HEADER_LEN;
+        loop {
+            match self.next_entry(self.active, offset, &mut entry_buffer)? {
+                Entry::End => break,
+                Entry::Corrupted { next } => offset = next,
+                Entry::Valid { key, len, next } => {
+                    //Only the latest entry of a key is copied, and removed keys are dropped
+                    let mut scratch = [0u8; ENTRY_BUFFER_LEN];
+                    let superseded = self.latest_entry(key, next, &mut scratch)?.is_some();
+                    if !superseded && len > 0 {
+                        let entry_len = Self::entry_len(len);
+                        let address = self.page_address(next_page) + next_write_offset;
+                        self.flash.write(address, &entry_buffer[..entry_len]).map_err(StoreError::Flash)?;
+                        next_write_offset += entry_len as u32;
+                    }
+                    offset = next;
+                }
+            }
+        }
+
+        self.write_page_magic(next_page)?;
+        self.active = next_page;
+        self.sequence = self.sequence.wrapping_add(1);
+        self.write_offset = next_write_offset;
+        Ok(())
+    }
+
+    //Finds the last valid entry for key in the active page, starting at offset. Returns its offset and data length
+    fn latest_entry(&mut self, key: u8, mut offset: u32, entry_buffer: &mut [u8; ENTRY_BUFFER_LEN]) -> Result<Option<(u32, usize)>, StoreError<F::Error>> {
+        let mut latest = None;
+        loop {
+            match self.next_entry(self.active, offset, entry_buffer)? {
+                Entry::End => return Ok(latest),
+                Entry::Corrupted { next } => offset = next,
+                Entry::Valid { key: entry_key, len, next } => {
+                    if entry_key == key {
+                        latest = Some((offset, len));
+                    }
+                    offset = next;
+                }
+            }
+        }
+    }
+
+    //Offset after the last entry of the active page, where the next entry will be written
+    fn end_of_entries(&mut self) -> Result<u32, StoreError<F::Error>> {
+        let mut entry_buffer = [0u8; ENTRY_BUFFER_LEN];
+        let mut offset = PAGE_HEADER_LEN;
+        loop {
+            match self.next_entry(self.active, offset, &mut entry_buffer)? {
+                Entry::End => return Ok(offset),
+                Entry::Corrupted { next } | Entry::Valid { next, .. } => offset = next
+            }
+        }
+    }
+
+    //Reads the entry at offset into entry_buffer
+    fn next_entry(&mut self, page: u32, offset: u32, entry_buffer: &mut [u8; ENTRY_BUFFER_LEN]) -> Result<Entry, StoreError<F::Error>> {
+        let page_size = F::ERASE_SIZE as u32;
+        if offset + Self::entry_len(0) as u32 > page_size {
+            return Ok(Entry::End);
+        }
+
+        let address = self.page_address(page) + offset;
+        let header_len = Self::align(ENTRY_HEADER_LEN);
+        self.flash.read(address, &mut entry_buffer[..header_len]).map_err(StoreError::Flash)?;
+
+        let key = entry_buffer[0];
+        let len = LittleEndian::read_u16(&entry_buffer[1..3]) as usize;
+        if key == ERASED_KEY && len == 0xFFFF {
+            return Ok(Entry::End);
+        }
+
+        //A length which was not fully written makes the rest of the page unusable
+        let entry_len = Self::entry_len(len);
+        if len > MAX_VALUE_LEN || offset + entry_len as u32 > page_size {
+            return Ok(Entry::Corrupted { next: page_size });
+        }
+
+        self.flash.read(address, &mut entry_buffer[..entry_len]).map_err(StoreError::Flash)?;
+        let crc = LittleEndian::read_u32(&entry_buffer[ENTRY_HEADER_LEN + len..ENTRY_HEADER_LEN + len + ENTRY_CRC_LEN]);
+        let next = offset + entry_len as u32;
+        if crc32(&entry_buffer[..ENTRY_HEADER_LEN + len]) == crc {
+            Ok(Entry::Valid { key, len, next })
+        } else {
+            Ok(Entry::Corrupted { next })
+        }
+    }
+
+    fn page_sequence(&mut self, page: u32) -> Result<Option<u32>, StoreError<F::Error>> {
+        let mut header = [0u8; PAGE_HEADER_LEN as usize];
+        self.flash.read(self.page_address(page), &mut header).map_err(StoreError::Flash)?;
+
+        if LittleEndian::read_u32(&header[4..8]) == PAGE_MAGIC {
+            Ok(Some(LittleEndian::read_u32(&header[0..4])))
+        } else {
+            Ok(None)
+        }
+    }
+
+    //Erases a page and writes its sequence number. The page is not valid until write_page_magic is called
+    fn format_page(&mut self, page: u32, sequence: u32) -> Result<(), StoreError<F::Error>> {
+        let address = self.page_address(page);
+        self.flash.erase(address, address + F::ERASE_SIZE as u32).map_err(StoreError::Flash)?;
+
+        let mut word = [0u8; 4];
+        LittleEndian::write_u32(&mut word, sequence);
+        self.flash.write(address, &word).map_err(StoreError::Flash)
+    }
+
+    fn write_page_magic(&mut self, page: u32) -> Result<(), StoreError<F::Error>> {
+        let mut word = [0u8; 4];
+        LittleEndian::write_u32(&mut word, PAGE_MAGIC);
+        self.flash.write(self.page_address(page) + 4, &word).map_err(StoreError::Flash)
+    }
+
+    fn encode_entry(key: u8, data: &[u8], entry_buffer: &mut [u8; ENTRY_BUFFER_LEN]) -> usize {
+        entry_buffer[0] = key;
+        LittleEndian::write_u16(&mut entry_buffer[1..3], data.len() as u16);
+        entry_buffer[ENTRY_HEADER_LEN..ENTRY_HEADER_LEN + data.len()].copy_from_slice(data);
+
+        let crc = crc32(&entry_buffer[..ENTRY_HEADER_LEN + data.len()]);
+        LittleEndian::write_u32(&mut entry_buffer[ENTRY_HEADER_LEN + data.len()..], crc);
+
+        Self::entry_len(data.len())
+    }
+
+    //Entry length including padding, so that every entry starts on a write (and read) boundary
+    fn entry_len(data_len: usize) -> usize {
+        Self::align(ENTRY_HEADER_LEN + data_len + ENTRY_CRC_LEN)
+    }
+
+    fn align(len: usize) -> usize {
+        let alignment = F::WRITE_SIZE.max(F::READ_SIZE);
+        len.div_ceil(alignment) * alignment
+    }
+
+    fn page_address(&self, page: u32) -> u32 {
+        self.base + page * F::ERASE_SIZE as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::{RamFlash, RamFlashError, RAM_FLASH_PAGE_SIZE};
+    use crate::sensor::imu::lsm9ds1::lsm9ds1_s::CalibrationInfo;
+    use super::*;
+
+    #[test]
+    fn blank_flash_is_formatted() {
+        let mut flash = RamFlash::new(2);
+        let mut store = RecordStore::mount(&mut flash, 0, 2).unwrap();
+
+        let mut buffer = [0u8; 8];
+        assert_eq!(store.read(0x10, &mut buffer), Ok(None));
+        assert_eq!(store.free_space(), RAM_FLASH_PAGE_SIZE - 8);
+        drop(store);
+
+        assert_eq!(flash.erase_counts, [1, 0]);
+    }
+
+    #[test]
+    fn latest_value_is_read() {
+        let mut flash = RamFlash::new(2);
+        let mut store = RecordStore::mount(&mut flash, 0, 2).unwrap();
+
+        store.write(0x10, &[1, 2, 3]).unwrap();
+        store.write(0x11, &[9]).unwrap();
+        store.write(0x10, &[4, 5]).unwrap();
+
+        let mut buffer = [0u8; 8];
+        assert_eq!(store.read(0x10, &mut buffer), Ok(Some(2)));
+        assert_eq!(&buffer[..2], &[4, 5]);
+        assert_eq!(store.read(0x11, &mut buffer), Ok(Some(1)));
+        assert_eq!(store.read(0x11, &mut buffer[..0]), Err(StoreError::BufferTooSmall));
+    }
+
+    #[test]
+    fn values_survive_remount() {
+        let mut flash = RamFlash::new(2);
+        let mut store = RecordStore::mount(&mut flash, 0, 2).unwrap();
+        store.write(0x10, &[1, 2, 3]).unwrap();
+        store.write(0x11, &[4]).unwrap();
+        store.remove(0x11).unwrap();
+        drop(store);
+
+        let mut store = RecordStore::mount(&mut flash, 0, 2).unwrap();
+        let mut buffer = [0u8; 8];
+        assert_eq!(store.read(0x10, &mut buffer), Ok(Some(3)));
+        assert_eq!(&buffer[..3], &[1, 2, 3]);
+        assert_eq!(store.read(0x11, &mut buffer), Ok(None));
+
+        //New entries are appended after the existing ones
+        store.write(0x12, &[5]).unwrap();
+        assert_eq!(store.read(0x10, &mut buffer), Ok(Some(3)));
+    }
+
+    #[test]
+    fn full_page_is_compacted_into_next_page() {
+        let mut flash = RamFlash::new(3);
+        let mut store = RecordStore::mount(&mut flash, 0, 3).unwrap();
+        store.write(0x10, &[0xAA; 20]).unwrap();
+        store.write(0x11, &[0xBB; 20]).unwrap();
+        store.remove(0x11).unwrap();
+
+        //Enough writes to go through every page several times
+        for i in 0..1000u32 {
+            store.write(0x12, &i.to_le_bytes()).unwrap();
+        }
+
+        let mut buffer = [0u8; 32];
+        assert_eq!(store.read(0x10, &mut buffer), Ok(Some(20)));
+        assert_eq!(&buffer[..20], &[0xAA; 20]);
+        assert_eq!(store.read(0x11, &mut buffer), Ok(None));
+        assert_eq!(store.read(0x12, &mut buffer), Ok(Some(4)));
+        assert_eq!(&buffer[..4], &999u32.to_le_bytes());
+        drop(store);
+
+        //Pages are erased in turn
+        let (min, max) = (flash.erase_counts.iter().min().unwrap(), flash.erase_counts.iter().max().unwrap());
+        assert!(*min > 1 && max - min <= 1, "{:?}", flash.erase_counts);
+
+        let mut store = RecordStore::mount(&mut flash, 0, 3).unwrap();
+        assert_eq!(store.read(0x12, &mut buffer), Ok(Some(4)));
+        assert_eq!(&buffer[..4], &999u32.to_le_bytes());
+    }
+
+    #[test]
+    fn power_loss_during_compaction_keeps_previous_page() {
+        let mut flash = RamFlash::new(2);
+        let mut store = RecordStore::mount(&mut flash, 0, 2).unwrap();
+        store.write(0x10, &[0xAA; 20]).unwrap();
+        let mut i = 0u32;
+        while store.free_space() >= 12 {
+            store.write(0x12, &i.to_le_bytes()).unwrap();
+            i += 1;
+        }
+        drop(store);
+
+        //The sequence number and one entry are written to the new page before the power is lost
+        flash.write_budget = Some(2);
+        let mut store = RecordStore::mount(&mut flash, 0, 2).unwrap();
+        assert_eq!(store.write(0x12, &[0xFF; 4]), Err(StoreError::Flash(RamFlashError::PowerLoss)));
+        drop(store);
+
+        flash.write_budget = None;
+        let mut store = RecordStore::mount(&mut flash, 0, 2).unwrap();
+        let mut buffer = [0u8; 32];
+        assert_eq!(store.read(0x10, &mut buffer), Ok(Some(20)));
+        assert_eq!(store.read(0x12, &mut buffer), Ok(Some(4)));
+        assert_eq!(&buffer[..4], &(i - 1).to_le_bytes());
+
+        //The interrupted page is erased again by the next compaction
+        store.write(0x12, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(store.read(0x12, &mut buffer), Ok(Some(4)));
+        assert_eq!(&buffer[..4], &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn corrupted_entry_is_skipped() {
+        let mut flash = RamFlash::new(2);
+        let mut store = RecordStore::mount(&mut flash, 0, 2).unwrap();
+        store.write(0x10, &[1, 2, 3, 4]).unwrap();
+        store.write(0x10, &[5, 6, 7, 8]).unwrap();
+        drop(store);
+
+        //Data byte of the second entry, after the page header and the 12 byte first entry
+        flash.data[8 + 12 + 3] = 0x00;
+
+        let mut store = RecordStore::mount(&mut flash, 0, 2).unwrap();
+        let mut buffer = [0u8; 8];
+        assert_eq!(store.read(0x10, &mut buffer), Ok(Some(4)));
+        assert_eq!(&buffer[..4], &[1, 2, 3, 4]);
+
+        store.write(0x10, &[9, 9, 9, 9]).unwrap();
+        assert_eq!(store.read(0x10, &mut buffer), Ok(Some(4)));
+        assert_eq!(&buffer[..4], &[9, 9, 9, 9]);
+    }
+
+    #[test]
+    fn calibration_is_saved_and_loaded() {
+        let mut flash = RamFlash::new(2);
+        let mut store = RecordStore::mount(&mut flash, 0, 2).unwrap();
+        assert_eq!(store.load::<CalibrationInfo>(), Ok(None));
+
+        let mut calibration = CalibrationInfo::default();
+        calibration.gyroscope.x_bias = 0.25;
+        calibration.magnetometer.z_offset = -300;
+        store.save(&calibration).unwrap();
+
+        assert_eq!(store.load::<CalibrationInfo>(), Ok(Some(calibration)));
+    }
+
+    #[test]
+    fn invalid_use_is_rejected() {
+        let mut flash = RamFlash::new(2);
+        assert!(matches!(RecordStore::mount(&mut flash, 0, 1), Err(StoreError::InvalidRegion)));
+        assert!(matches!(RecordStore::mount(&mut flash, 512, 2), Err(StoreError::InvalidRegion)));
+        assert!(matches!(RecordStore::mount(&mut flash, 1024, 2), Err(StoreError::OutOfRange)));
+
+        let mut store = RecordStore::mount(&mut flash, 0, 2).unwrap();
+        assert_eq!(store.write(0xFF, &[1]), Err(StoreError::InvalidKey));
+        assert_eq!(store.write(0x10, &[]), Err(StoreError::InvalidLength));
+        assert_eq!(store.write(0x10, &[0; MAX_VALUE_LEN + 1]), Err(StoreError::InvalidLength));
+
+        //Four values of the largest size fill a page
+        for key in 0..4 {
+            store.write(key, &[key; MAX_VALUE_LEN]).unwrap();
+        }
+        assert_eq!(store.write(4, &[4; MAX_VALUE_LEN]), Err(StoreError::NoSpace));
+        let mut buffer = [0u8; MAX_VALUE_LEN];
+        assert_eq!(store.read(3, &mut buffer), Ok(Some(MAX_VALUE_LEN)));
+    }
+}
@@ -11,13 +11,13 @@ pub struct USB {
 }
 
 impl USB {
-    pub fn new<'a, TxPin, RxPin>(usart: pac::USART1, rx_pin: RxPin, tx_pin: TxPin, clocks: &Clocks) -> Self 
+    pub fn new<TxPin, RxPin>(usart: pac::USART1, rx_pin: RxPin, tx_pin: TxPin, clocks: &Clocks) -> Self 
     where 
         TxPin: Into<<pac::USART1 as CommonPins>::Tx<PushPull>>,
         RxPin: Into<<pac::USART1 as CommonPins>::Rx<PushPull>>
     {
         let serial_config = Config::default().baudrate(9600.bps());
-        let mut serial = Serial::new(usart, (tx_pin, rx_pin), serial_config, &clocks).unwrap();
+        let mut serial = Serial::new(usart, (tx_pin, rx_pin), serial_config, clocks).unwrap();
 
         USB {
             serial